@@ -1,12 +1,136 @@
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use pfa::{builder::PfaBuilder, shared::DataFlags};
 
+#[path = "src/asset_check.rs"]
+mod asset_check;
+
 fn main() {
+    let mut assets = vec![];
+    collect(Path::new("./bundle"), &mut assets);
+    assets.sort();
+
+    let mut errors = vec![];
+    let mut ids: Vec<(String, String)> = vec![];
+    for path in &assets {
+        let bundle_path = bundle_path(path);
+        if let Err(e) = validate(path) {
+            errors.push(format!("{bundle_path}: {e}"));
+        }
+        let id = asset_id(&bundle_path);
+        if let Some((_, other)) = ids.iter().find(|(other_id, _)| *other_id == id) {
+            errors.push(format!(
+                "{bundle_path} and {other} would both be called AssetId::{id}, rename one of them"
+            ));
+        }
+        ids.push((id, bundle_path));
+    }
+    if !errors.is_empty() {
+        panic!("invalid assets in ./bundle:\n  {}", errors.join("\n  "));
+    }
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR").unwrap());
+    let staged = out_dir.join("bundle");
+    stage(&assets, &staged);
     let mut b = PfaBuilder::new("bundle");
-    b.include_directory("./bundle", DataFlags::auto()).unwrap();
+    b.include_directory(&staged.to_string_lossy(), DataFlags::auto())
+        .unwrap();
     let mut file = File::create("./src/bundle.pfa").unwrap();
     file.write_all(&b.build().unwrap()).unwrap();
 
+    std::fs::write(out_dir.join("assets.rs"), generate(&ids)).unwrap();
+
     println!("cargo:rerun-if-changed=bundle");
 }
+
+fn collect(dir: &Path, assets: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        // Hidden files like `.DS_Store` are left behind by file managers, not meant as assets
+        if path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect(&path, assets);
+        } else {
+            assets.push(path);
+        }
+    }
+}
+
+/// Copies the checked files to `dir`, so the archive holds exactly those and none of the
+/// hidden files `collect` skipped.
+fn stage(assets: &[PathBuf], dir: &Path) {
+    if dir.exists() {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+    for path in assets {
+        let target = dir.join(path.strip_prefix("./bundle").unwrap());
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        std::fs::copy(path, target).unwrap();
+    }
+}
+
+/// The path the game looks the file up by, like `/music/calm.ogg`.
+fn bundle_path(path: &Path) -> String {
+    path.strip_prefix("./bundle")
+        .unwrap()
+        .components()
+        .map(|c| format!("/{}", c.as_os_str().to_string_lossy()))
+        .collect()
+}
+
+/// `/music/calm.ogg` becomes `MusicCalm`.
+fn asset_id(bundle_path: &str) -> String {
+    let without_extension = bundle_path
+        .rsplit_once('.')
+        .map_or(bundle_path, |(stem, _)| stem);
+    let id: String = without_extension
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let mut chars = word.chars();
+            let first = chars.next().unwrap().to_ascii_uppercase();
+            format!("{first}{}", chars.as_str().to_ascii_lowercase())
+        })
+        .collect();
+    if id.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("Asset{id}")
+    } else {
+        id
+    }
+}
+
+fn validate(path: &Path) -> Result<(), String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    asset_check::check(&path.to_string_lossy(), &bytes)?;
+    if asset_id(&bundle_path(path)).is_empty() {
+        return Err("file name needs at least one letter or digit".to_string());
+    }
+    Ok(())
+}
+
+fn generate(ids: &[(String, String)]) -> String {
+    let mut out = String::from(
+        "/// Every file in `./bundle`, generated by `build.rs`.\n\
+         #[allow(dead_code)] // Not every file is referenced from code\n\
+         #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]\n\
+         pub enum AssetId {\n",
+    );
+    for (id, _) in ids {
+        out += &format!("    {id},\n");
+    }
+    out += "}\n\nimpl AssetId {\n    pub fn path(self) -> &'static str {\n        match self {\n";
+    for (id, path) in ids {
+        out += &format!("            Self::{id} => {path:?},\n");
+    }
+    out += "        }\n    }\n}\n";
+    out
+}
@@ -0,0 +1,34 @@
+//! Checks on audio files, shared by `build.rs` for the bundle and by `mods.rs`
+//! for the files mods mount over it.
+
+/// Anything bigger than this is almost certainly a mistake in an asset.
+pub const MAX_ASSET_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Checks the size, and that the file is an Ogg, WAV or PNG file with a matching header,
+/// going by the extension of `path`.
+pub fn check(path: &str, bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() as u64 > MAX_ASSET_SIZE {
+        return Err(format!(
+            "{} bytes is over the {MAX_ASSET_SIZE} byte limit",
+            bytes.len()
+        ));
+    }
+    let extension = path.rsplit_once('.').map(|(_, ext)| ext);
+    let problem = match extension {
+        Some("ogg") if !bytes.starts_with(b"OggS") => Some("not an Ogg file"),
+        // The first page holds the Vorbis identification header
+        Some("ogg") if !bytes.windows(7).take(64).any(|w| w == b"\x01vorbis") => {
+            Some("Ogg file without a Vorbis stream")
+        }
+        Some("wav") if !bytes.starts_with(b"RIFF") || bytes.get(8..12) != Some(b"WAVE") => {
+            Some("not a WAV file")
+        }
+        Some("png") if !bytes.starts_with(b"\x89PNG\r\n\x1a\n") => Some("not a PNG file"),
+        Some("ogg" | "wav" | "png") => None,
+        _ => Some("unsupported file type, use .ogg, .wav or .png"),
+    };
+    match problem {
+        Some(problem) => Err(problem.to_string()),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use egui_macroquad::macroquad::audio::{
+    load_sound_from_bytes, play_sound, set_sound_volume, stop_sound, PlaySoundParams, Sound,
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    assets::AssetId,
+    hot_reload,
+    settings::{self, Settings},
+    sound_event::SoundEvent,
+    BUNDLE,
+};
+
+/// Looping music, all playing at once and faded in and out by mood.
+const TRACKS: &[AssetId] = &[
+    AssetId::MusicLobby,
+    AssetId::MusicAmbience,
+    AssetId::MusicTension,
+];
+/// Full volume per second.
+const FADE_SPEED: f32 = 0.8;
+/// How long the music stays out after a shot.
+const SHOT_SILENCE: Duration = Duration::from_secs(3);
+/// How loud other sounds are while ducked.
+const DUCK_LEVEL: f32 = 0.3;
+const DUCK_HOLD: Duration = Duration::from_millis(600);
+const DUCK_RELEASE: Duration = Duration::from_millis(400);
+
+/// Decides which music tracks play.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Mood {
+    #[default]
+    Lobby,
+    /// At a table, `tension` going from 0 while nobody holds a key to 1 just before the revolver fires.
+    Table { tension: f32 },
+}
+
+impl Mood {
+    fn level(self, track: AssetId) -> f32 {
+        match (self, track) {
+            (Self::Lobby, AssetId::MusicLobby) => 1.0,
+            (Self::Table { tension }, AssetId::MusicAmbience) => 1.0 - 0.6 * tension,
+            (Self::Table { tension }, AssetId::MusicTension) => tension,
+            _ => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Effects,
+    Music,
+}
+
+impl Channel {
+    /// Sounds in the bundle's `/music` folder are music, everything else is an effect.
+    fn of(path: &str) -> Self {
+        if path.starts_with("/music/") {
+            Self::Music
+        } else {
+            Self::Effects
+        }
+    }
+}
+
+/// Which bundle sound plays for each event. Events without an entry stay silent.
+#[derive(Debug, Clone)]
+pub struct SoundPack {
+    sounds: HashMap<SoundEvent, String>,
+}
+
+impl Default for SoundPack {
+    fn default() -> Self {
+        let sounds = [
+            (SoundEvent::Cock, AssetId::Cock),
+            (SoundEvent::Shot, AssetId::Shoot),
+            (SoundEvent::Misfire, AssetId::Missfire),
+        ];
+        Self {
+            sounds: sounds
+                .into_iter()
+                .map(|(event, asset)| (event, asset.path().to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl SoundPack {
+    pub fn path(&self, event: SoundEvent) -> Option<&str> {
+        self.sounds.get(&event).map(String::as_str)
+    }
+}
+
+#[derive(Default)]
+struct Mixer {
+    sounds: HashMap<String, Sound>,
+    pack: SoundPack,
+    /// Server sounds waiting to be decoded on the next `update`.
+    incoming: Option<Vec<(SoundEvent, Vec<u8>)>>,
+    /// The ducking sound that played last, and when.
+    ducker: Option<(String, Instant)>,
+    mood: Mood,
+    /// Current volume of each music track, following `mood`.
+    levels: HashMap<String, f32>,
+    last_update: Option<Instant>,
+    last_shot: Option<Instant>,
+}
+
+lazy_static! {
+    static ref MIXER: Mutex<Mixer> = Mutex::new(Mixer::default());
+}
+
+fn is_sound(path: &str) -> bool {
+    path.ends_with(".ogg") || path.ends_with(".wav")
+}
+
+/// Decodes every sound in the bundle up front so playing one never stalls a frame.
+pub async fn preload() {
+    let mut sounds = HashMap::new();
+    for (path, bytes) in BUNDLE.iter() {
+        if !is_sound(path) {
+            continue;
+        }
+        match load_sound_from_bytes(bytes).await {
+            Ok(sound) => {
+                sounds.insert(path.clone(), sound);
+            }
+            Err(e) => eprintln!("[Audio] could not decode {path}: {e}"),
+        }
+    }
+
+    let mut mixer = MIXER.lock().unwrap();
+    mixer.sounds = sounds;
+    for track in TRACKS {
+        if let Some(sound) = mixer.sounds.get(track.path()) {
+            start_track(*sound);
+        }
+    }
+    for event in SoundEvent::ALL {
+        if let Some(path) = mixer.pack.path(*event) {
+            if !mixer.sounds.contains_key(path) {
+                eprintln!(
+                    "[Audio] sound pack plays {path} for {event:?}, but it is not in the bundle"
+                );
+            }
+        }
+    }
+}
+
+/// Tracks start silent and loop forever, `update` fades them in.
+fn start_track(sound: Sound) {
+    play_sound(
+        sound,
+        PlaySoundParams {
+            looped: true,
+            volume: 0.0,
+        },
+    );
+}
+
+pub fn set_mood(mood: Mood) {
+    MIXER.lock().unwrap().mood = mood;
+}
+
+/// Plays the sound pack's sound for `event`, if it has one.
+pub fn play(event: SoundEvent) {
+    let mut mixer = MIXER.lock().unwrap();
+    if event == SoundEvent::Shot {
+        // The music cuts out with the bang and fades back in later
+        mixer.last_shot = Some(Instant::now());
+        mixer.levels.values_mut().for_each(|level| *level = 0.0);
+    }
+    let Some(path) = mixer.pack.path(event).map(str::to_string) else {
+        return;
+    };
+    let Some(sound) = mixer.sounds.get(&path).copied() else {
+        return;
+    };
+    if event.ducks() {
+        mixer.ducker = Some((path.clone(), Instant::now()));
+    }
+    let volume = mixer.volume(&path, &settings::get());
+    set_sound_volume(sound, volume);
+    play_sound(
+        sound,
+        PlaySoundParams {
+            looped: false,
+            volume,
+        },
+    );
+}
+
+/// Plays these sounds instead of the built-in ones for their events. An empty list goes back to the built-in pack.
+pub fn use_pack(sounds: Vec<(SoundEvent, Vec<u8>)>) {
+    MIXER.lock().unwrap().incoming = Some(sounds);
+}
+
+/// Server sounds are kept under a path no bundle file can have.
+fn pack_path(event: SoundEvent) -> String {
+    format!("pack:{event:?}")
+}
+
+/// Switches sound packs, then reapplies volumes following settings changes and ducking. Called once a frame.
+pub async fn update() {
+    let incoming = MIXER.lock().unwrap().incoming.take();
+    if let Some(incoming) = incoming {
+        let mut pack = SoundPack::default();
+        let mut decoded = vec![];
+        for (event, bytes) in incoming {
+            match load_sound_from_bytes(&bytes).await {
+                Ok(sound) => {
+                    pack.sounds.insert(event, pack_path(event));
+                    decoded.push((pack_path(event), sound));
+                }
+                Err(e) => eprintln!("[Audio] could not decode the server's {event:?} sound: {e}"),
+            }
+        }
+        let mut mixer = MIXER.lock().unwrap();
+        mixer.sounds.retain(|path, _| !path.starts_with("pack:"));
+        mixer.sounds.extend(decoded);
+        mixer.pack = pack;
+    }
+
+    for (path, bytes) in hot_reload::take_changes() {
+        if !is_sound(&path) {
+            continue;
+        }
+        let sound = match bytes {
+            Some(bytes) => match load_sound_from_bytes(&bytes).await {
+                Ok(sound) => Some(sound),
+                Err(e) => {
+                    eprintln!("[Audio] could not decode {path}, keeping the old sound: {e}");
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let mut mixer = MIXER.lock().unwrap();
+        let old = match sound {
+            Some(sound) => mixer.sounds.insert(path.clone(), sound),
+            None => mixer.sounds.remove(&path),
+        };
+        if TRACKS.iter().any(|track| track.path() == path) {
+            if let Some(old) = old {
+                stop_sound(old);
+            }
+            if let Some(sound) = sound {
+                start_track(sound);
+            }
+        }
+    }
+
+    let settings = settings::get();
+    let mut mixer = MIXER.lock().unwrap();
+    mixer.fade_music();
+    for (path, sound) in &mixer.sounds {
+        set_sound_volume(*sound, mixer.volume(path, &settings));
+    }
+}
+
+impl Mixer {
+    fn fade_music(&mut self) {
+        let now = Instant::now();
+        let dt = self
+            .last_update
+            .map_or(0.0, |last| (now - last).as_secs_f32());
+        self.last_update = Some(now);
+        let silenced = self
+            .last_shot
+            .is_some_and(|shot| shot.elapsed() < SHOT_SILENCE);
+
+        for track in TRACKS {
+            let target = if silenced {
+                0.0
+            } else {
+                self.mood.level(*track)
+            };
+            let level = self.levels.entry(track.path().to_string()).or_insert(0.0);
+            let step = FADE_SPEED * dt;
+            *level = if *level < target {
+                (*level + step).min(target)
+            } else {
+                (*level - step).max(target)
+            };
+        }
+    }
+
+    fn duck(&self, path: &str) -> f32 {
+        let Some((ducker, since)) = &self.ducker else {
+            return 1.0;
+        };
+        if ducker == path {
+            return 1.0;
+        }
+        let elapsed = since.elapsed();
+        if elapsed < DUCK_HOLD {
+            return DUCK_LEVEL;
+        }
+        let release = ((elapsed - DUCK_HOLD).as_secs_f32() / DUCK_RELEASE.as_secs_f32()).min(1.0);
+        DUCK_LEVEL + (1.0 - DUCK_LEVEL) * release
+    }
+
+    fn volume(&self, path: &str, settings: &Settings) -> f32 {
+        if settings.muted {
+            return 0.0;
+        }
+        let channel = match Channel::of(path) {
+            Channel::Effects => settings.effects_volume,
+            Channel::Music => settings.music_volume,
+        };
+        let level = self.levels.get(path).copied().unwrap_or(1.0);
+        settings.master_volume * channel * level * self.duck(path)
+    }
+}
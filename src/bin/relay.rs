@@ -0,0 +1,179 @@
+//! Forwards packets between a server and its players when neither side can
+//! accept inbound connections. Servers register and get a relay code, players
+//! join with that code, and the relay pairs each player with a fresh
+//! connection opened by the server.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Result};
+use rand::seq::SliceRandom;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+#[path = "../framing.rs"]
+mod framing;
+#[path = "../relay_protocol.rs"]
+mod relay_protocol;
+
+use relay_protocol::{RelayHello, RelayReply, RELAY_PORT};
+
+const CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const CODE_LEN: usize = 6;
+
+#[derive(Debug)]
+struct RelayHost {
+    control: mpsc::Sender<RelayReply>,
+    /// Players waiting for the server, by a random token only the server gets told.
+    pending: HashMap<u128, oneshot::Sender<TcpStream>>,
+}
+
+#[derive(Debug, Default)]
+struct Relay {
+    hosts: Mutex<HashMap<String, RelayHost>>,
+}
+
+#[tokio::main]
+async fn main() {
+    let addr = std::env::args()
+        .nth(1)
+        .unwrap_or(format!("0.0.0.0:{RELAY_PORT}"));
+    let tcp = TcpListener::bind(&addr).await.unwrap();
+    println!("Relay listening on {addr}");
+
+    let relay = Arc::new(Relay::default());
+    loop {
+        let Ok((stream, sock)) = tcp.accept().await else {
+            continue;
+        };
+        let relay = relay.clone();
+        tokio::spawn(async move {
+            if let Err(e) = relay.handle(stream).await {
+                println!("[{sock}] {e}");
+            }
+        });
+    }
+}
+
+impl Relay {
+    async fn handle(self: Arc<Self>, mut stream: TcpStream) -> Result<()> {
+        match relay_protocol::recv::<RelayHello>(&mut stream).await? {
+            RelayHello::Host => self.host(stream).await,
+            RelayHello::Join(code) => self.join(stream, &code).await,
+            RelayHello::Accept(code, session) => {
+                let mut hosts = self.hosts.lock().unwrap();
+                let waiting = hosts
+                    .get_mut(&code)
+                    .and_then(|host| host.pending.remove(&session))
+                    .ok_or(anyhow!("no such session waiting on {code}"))?;
+                let _ = waiting.send(stream);
+                Ok(())
+            }
+        }
+    }
+
+    /// Picks an unused relay code and registers the server under it in one go.
+    fn register(&self, control: mpsc::Sender<RelayReply>) -> String {
+        let mut hosts = self.hosts.lock().unwrap();
+        let code = loop {
+            let code = (0..CODE_LEN)
+                .map(|_| *CODE_CHARS.choose(&mut rand::thread_rng()).unwrap() as char)
+                .collect::<String>();
+            if !hosts.contains_key(&code) {
+                break code;
+            }
+        };
+        hosts.insert(
+            code.clone(),
+            RelayHost {
+                control,
+                pending: HashMap::new(),
+            },
+        );
+        code
+    }
+
+    async fn host(&self, stream: TcpStream) -> Result<()> {
+        let (tx, mut rx) = mpsc::channel(64);
+        let code = self.register(tx.clone());
+        println!("Server registered as {code}");
+        tx.send(RelayReply::Hosted(code.clone())).await?;
+
+        let (mut read, mut write) = stream.into_split();
+        tokio::select! {
+            _ = async {
+                while let Some(reply) = rx.recv().await {
+                    if relay_protocol::send(&mut write, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            } => {}
+            // The server never sends anything after registering, so any read ending means it left
+            _ = framing::read_frame(&mut read) => {}
+        }
+
+        self.hosts.lock().unwrap().remove(&code);
+        println!("Server {code} left");
+        Ok(())
+    }
+
+    async fn join(&self, mut stream: TcpStream, code: &str) -> Result<()> {
+        let code = code.trim().to_uppercase();
+        // Whoever knows the token can take the player's stream, so it must not be guessable
+        let session = rand::random::<u128>();
+        let (tx, rx) = oneshot::channel();
+        let control = {
+            let mut hosts = self.hosts.lock().unwrap();
+            hosts.get_mut(&code).map(|host| {
+                host.pending.insert(session, tx);
+                host.control.clone()
+            })
+        };
+        let forget_session = || {
+            if let Some(host) = self.hosts.lock().unwrap().get_mut(&code) {
+                host.pending.remove(&session);
+            }
+        };
+
+        let Some(control) = control else {
+            let reply = RelayReply::Error(format!("No server is using the relay code {code}"));
+            relay_protocol::send(&mut stream, &reply).await?;
+            return Ok(());
+        };
+        if let Err(e) = control.send(RelayReply::Incoming(session)).await {
+            forget_session();
+            return Err(e.into());
+        }
+
+        let Ok(Ok(server)) = tokio::time::timeout(tokio::time::Duration::from_secs(5), rx).await
+        else {
+            forget_session();
+            let reply = RelayReply::Error("The server did not pick up the connection".to_string());
+            relay_protocol::send(&mut stream, &reply).await?;
+            return Ok(());
+        };
+
+        relay_protocol::send(&mut stream, &RelayReply::Connected).await?;
+        let (mut client_read, mut client_write) = stream.into_split();
+        let (mut server_read, mut server_write) = server.into_split();
+        tokio::select! {
+            res = forward(&mut client_read, &mut server_write) => res,
+            res = forward(&mut server_read, &mut client_write) => res,
+        }
+    }
+}
+
+async fn forward(
+    from: &mut (impl AsyncRead + Unpin),
+    to: &mut (impl AsyncWrite + Unpin),
+) -> Result<()> {
+    loop {
+        let frame = framing::read_frame(from).await?;
+        framing::write_frame(to, &frame).await?;
+    }
+}
@@ -1,9 +1,20 @@
 use serde::{Deserialize, Serialize};
 
+use crate::{server::RoomSettings, sound_pack, tournament::BracketMode};
+
+/// Bumped whenever packets change in a way older builds can't read.
+pub const PROTOCOL_VERSION: u32 = 4;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum C2sPacket {
-    CreatePlayer(String),
+    CreatePlayer(u32, String),
+    QueryRooms,
+    CreateRoom(RoomSettings),
+    JoinRoom(String, Option<String>),
     KeyPress(char),
     KeyRelease(char),
     HostStartGame,
+    HostStartTournament(usize, BracketMode),
+    /// Asks for the sound pack asset with this hash, starting at an offset.
+    RequestAssetChunk(sound_pack::Hash, u32),
 }
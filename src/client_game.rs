@@ -1,19 +1,27 @@
-use egui_macroquad::{
-    egui,
-    macroquad::{
-        audio::{load_sound_from_bytes, play_sound_once},
-        input::KeyCode,
-        prelude::*,
-    },
-};
+use std::time::{Duration, Instant};
+
+use egui_macroquad::{egui, macroquad::prelude::*};
 use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    c2s_packet::C2sPacket, kicked::KickedState, main_menu::MainMenuState, s2c_packet::S2cPacket,
-    server::Game, GameState, BUNDLE,
+    audio::{self, Mood},
+    c2s_packet::C2sPacket,
+    kicked::KickedState,
+    main_menu::MainMenuState,
+    s2c_packet::S2cPacket,
+    server::Game,
+    settings::{self, Penalty},
+    settings_scene,
+    sound_event::SoundEvent,
+    sound_pack,
+    tournament::{Bracket, BracketMode},
+    GameState,
 };
 use anyhow::Result;
 
+/// Seconds the screen edge flashes for each sound.
+const FLASH_TIME: f32 = 0.4;
+
 pub struct ClientGameState {
     pub tx: Sender<C2sPacket>,
     pub rx: Receiver<Result<S2cPacket>>,
@@ -21,11 +29,22 @@ pub struct ClientGameState {
     pub host: bool,
     pub game: Game,
     pub username: String,
+    pub bracket: Option<Bracket>,
+    /// Sounds heard recently, shown on screen when captions are on.
+    pub captions: Vec<(SoundEvent, Instant)>,
+    /// Key being held down with the mouse or a finger.
+    pub pointer_key: Option<char>,
+    /// Whether the pointer was over an egui window last frame, so clicks there don't pull keys.
+    pub pointer_over_ui: bool,
 }
 
 impl ClientGameState {
     async fn handle_packet(&mut self, pack: S2cPacket) -> Option<GameState> {
         match pack {
+            S2cPacket::SyncServerInfo(_)
+            | S2cPacket::SyncRoomList(_)
+            | S2cPacket::SyncRoom(_)
+            | S2cPacket::JoinRoomFailed(_) => None,
             S2cPacket::SyncPlayerList(host, list) => {
                 self.host = host;
                 self.players = list;
@@ -39,37 +58,66 @@ impl ClientGameState {
                 self.game = game;
                 None
             }
+            S2cPacket::SyncBracket(bracket) => {
+                self.bracket = Some(bracket);
+                None
+            }
             S2cPacket::KillYourselfNow => {
-                #[cfg(windows)]
-                {
-                    bsod::bsod(); // Goodbye cruel world...
+                match settings::get().penalty {
+                    Penalty::Bsod => {
+                        #[cfg(windows)]
+                        {
+                            bsod::bsod(); // Goodbye cruel world...
+                        }
+                    }
+                    Penalty::CloseGame => std::process::exit(0),
+                    Penalty::Nothing => {}
                 }
                 None
             }
-            S2cPacket::PlaySound(sound_path) => {
-                if let Some(bytes) = BUNDLE.get(&sound_path) {
-                    if let Ok(sound) = load_sound_from_bytes(bytes).await {
-                        play_sound_once(sound);
-                    }
-                } else {
-                    eprintln!("ERROR: server requested to sync sound {sound_path}, but it was not found in bundle");
+            S2cPacket::PlaySound(event) => {
+                audio::play(event);
+                if settings::get().captions {
+                    self.captions.push((event, Instant::now()));
                 }
-
+                None
+            }
+            S2cPacket::AssetChunk(sha256, offset, bytes) => {
+                sound_pack::receive(&self.tx, sha256, offset, bytes);
                 None
             }
         }
     }
 
+    /// Tension rises as soon as someone starts holding a key and peaks just before it fires.
+    pub fn mood(&self) -> Mood {
+        // A grabbed key starts at 0 progress, so tension starts as soon as one is in the map
+        let tension = match self.game.progress.values().max() {
+            Some(held) => 0.5 + 0.5 * *held as f32 / 255.0,
+            None => 0.0,
+        };
+        Mood::Table { tension }
+    }
+
     pub async fn tick(mut self) -> GameState {
         let mut new_game_state = None;
 
-        if let Ok(Ok(packet)) = self.rx.try_recv() {
-            if let Some(new_state) = self.handle_packet(packet).await {
-                return new_state;
+        match self.rx.try_recv() {
+            Ok(Ok(packet)) => {
+                if let Some(new_state) = self.handle_packet(packet).await {
+                    return new_state;
+                }
             }
+            Ok(Err(e)) => {
+                return GameState::Kicked(KickedState {
+                    message: format!("Lost connection to the server ({e})"),
+                });
+            }
+            Err(_) => {}
         }
 
         self.render().await;
+        self.render_captions();
         egui_macroquad::ui(|ctx| {
             egui::Window::new("In-game").show(ctx, |ui| {
                 ui.label(format!("Queue: {}", self.game.queue.join(", ")));
@@ -77,6 +125,14 @@ impl ClientGameState {
                     new_game_state = Some(GameState::MainMenu(MainMenuState::new()));
                 }
             });
+
+            if let Some(bracket) = &self.bracket {
+                egui::Window::new("Bracket").show(ctx, |ui| {
+                    bracket_view(ui, bracket, &self.username);
+                });
+            }
+            settings_scene::overlay(ctx);
+            self.pointer_over_ui = ctx.is_pointer_over_area();
         });
         egui_macroquad::draw();
 
@@ -87,7 +143,86 @@ impl ClientGameState {
         }
     }
 
-    async fn render(&self) {
+    /// Flashes the screen edge and lists recent sounds along the bottom, newest last.
+    fn render_captions(&mut self) {
+        let settings = settings::get();
+        let duration = Duration::from_secs_f32(settings.caption_duration);
+        self.captions.retain(|(_, at)| at.elapsed() < duration);
+        if !settings.captions {
+            return;
+        }
+
+        let mut y = screen_height() - settings.caption_size;
+        for (event, at) in self.captions.iter().rev() {
+            let (text, color) = caption(*event);
+            let age = at.elapsed().as_secs_f32();
+            let fade = 1.0 - age / duration.as_secs_f32();
+
+            // A short flash around the screen so the cue is noticed even when looking at the keys
+            let flash = 1.0 - age / FLASH_TIME;
+            if flash > 0.0 {
+                let flash_color = Color::new(color.r, color.g, color.b, flash * 0.8);
+                draw_rectangle_lines(0.0, 0.0, screen_width(), screen_height(), 24.0, flash_color);
+                draw_icon(*event, screen_width() / 2.0, 80.0, 48.0, flash_color);
+            }
+
+            let dim = measure_text(text, None, settings.caption_size as u16, 1.0);
+            let x = (screen_width() - dim.width) / 2.0;
+            draw_rectangle(
+                x - 8.0,
+                y - dim.height - 4.0,
+                dim.width + 16.0,
+                dim.height + 12.0,
+                Color::new(0.0, 0.0, 0.0, 0.6 * fade),
+            );
+            draw_text(
+                text,
+                x,
+                y,
+                settings.caption_size,
+                Color::new(color.r, color.g, color.b, fade),
+            );
+            y -= settings.caption_size * 1.3;
+        }
+    }
+
+    async fn render(&mut self) {
+        if let Some(bracket) = &self.bracket {
+            if bracket.is_out(&self.username) {
+                centered_text_at(
+                    "You're out, watching the bracket...",
+                    screen_width() / 2.0,
+                    60.0,
+                    50.0,
+                    WHITE,
+                );
+                return;
+            }
+            if bracket.is_safe(&self.username) {
+                centered_text_at(
+                    "You're safe, watching the bracket...",
+                    screen_width() / 2.0,
+                    60.0,
+                    50.0,
+                    WHITE,
+                );
+                return;
+            }
+            let seated = bracket
+                .table_of(&self.username)
+                .is_some_and(|table| bracket.is_open(table));
+            if !seated {
+                centered_text_at(
+                    "Waiting for the other tables...",
+                    screen_width() / 2.0,
+                    60.0,
+                    50.0,
+                    WHITE,
+                );
+                return;
+            }
+        }
+
         let Some(turn) = self.game.queue.first() else {
             return;
         };
@@ -108,27 +243,35 @@ impl ClientGameState {
         let mut basis_x = sx;
         let size = screen_width() / 20.0;
         let padding = 10.0;
-        let keys = [
-            vec!['Q', 'W', 'E', 'R', 'T', 'Y', 'U', 'I', 'O', 'P'],
-            vec!['A', 'S', 'D', 'F', 'G', 'H', 'J', 'K', 'L'],
-            vec!['Z', 'X', 'C', 'V', 'B', 'N', 'M'],
-        ];
-
-        for (i, row) in keys.iter().enumerate() {
-            for key in row.iter() {
-                if self.game.fired.contains(key) {
+        let layout = settings::get().keyboard_layout;
+        let (mouse_x, mouse_y) = mouse_position();
+        let mut hovered = None;
+
+        for (i, row) in layout.rows().iter().enumerate() {
+            for key in row.chars() {
+                // Blank slots keep the letters on the physical keys they belong to
+                if key == ' ' || self.game.fired.contains(&key) {
                     sx += size + padding;
                     continue;
                 }
-                let fill_percent = *self.game.progress.get(key).unwrap_or(&0) as f32 / 255.0;
+                let fill_percent = *self.game.progress.get(&key).unwrap_or(&0) as f32 / 255.0;
                 draw_rectangle(sx, sy + size, size, -size * fill_percent, GRAY);
-                draw_rectangle_lines(sx, sy, size, size, 5.0, GRAY);
-                if is_key_pressed(char_to_keycode(*key).unwrap()) {
-                    let _ = self.tx.send(C2sPacket::KeyPress(*key)).await;
+                let outline = if self.pointer_key == Some(key) {
+                    WHITE
+                } else {
+                    GRAY
+                };
+                draw_rectangle_lines(sx, sy, size, size, 5.0, outline);
+                if (sx..sx + size).contains(&mouse_x) && (sy..sy + size).contains(&mouse_y) {
+                    hovered = Some(key);
+                }
+                let keycode = layout.keycode(key).unwrap();
+                if is_key_pressed(keycode) && !settings_scene::is_open() {
+                    let _ = self.tx.send(C2sPacket::KeyPress(key)).await;
                 }
 
-                if is_key_released(char_to_keycode(*key).unwrap()) {
-                    let _ = self.tx.send(C2sPacket::KeyRelease(*key)).await;
+                if is_key_released(keycode) {
+                    let _ = self.tx.send(C2sPacket::KeyRelease(key)).await;
                 }
                 centered_text_at(
                     &key.to_string(),
@@ -144,41 +287,159 @@ impl ClientGameState {
             basis_x += (size / 4.0) * (i as f32 + 1.0);
             sx = basis_x;
         }
+
+        self.update_pointer(hovered).await;
+    }
+
+    /// Pulls a key while it's held with the mouse, or a finger since macroquad turns touches into
+    /// mouse input. Sliding off the key lets go of it, a new key needs a new press.
+    async fn update_pointer(&mut self, hovered: Option<char>) {
+        // A fired key leaves the grid and the server stops tracking it, so there is nothing to release
+        if self
+            .pointer_key
+            .is_some_and(|key| self.game.fired.contains(&key))
+        {
+            self.pointer_key = None;
+        }
+        let blocked = self.pointer_over_ui || settings_scene::is_open();
+        let held = is_mouse_button_down(MouseButton::Left) && !blocked;
+        let next = match self.pointer_key {
+            Some(key) if held && hovered == Some(key) => Some(key),
+            _ if is_mouse_button_pressed(MouseButton::Left) && !blocked => hovered,
+            _ => None,
+        };
+        if next == self.pointer_key {
+            return;
+        }
+
+        if let Some(key) = self.pointer_key {
+            let _ = self.tx.send(C2sPacket::KeyRelease(key)).await;
+        }
+        if let Some(key) = next {
+            let _ = self.tx.send(C2sPacket::KeyPress(key)).await;
+        }
+        self.pointer_key = next;
+    }
+}
+
+/// What a sound says on screen, and the color its flash and text use.
+fn caption(event: SoundEvent) -> (&'static str, Color) {
+    match event {
+        SoundEvent::Cock => ("[revolver cocks]", YELLOW),
+        SoundEvent::Shot => ("[gunshot]", RED),
+        SoundEvent::Misfire => ("[click, misfire]", LIGHTGRAY),
+        SoundEvent::TurnStart => ("[your turn]", SKYBLUE),
+        SoundEvent::Win => ("[victory]", GREEN),
     }
 }
 
-fn char_to_keycode(chr: char) -> Option<KeyCode> {
-    match chr.to_ascii_lowercase() {
-        'a' => Some(KeyCode::A),
-        'b' => Some(KeyCode::B),
-        'c' => Some(KeyCode::C),
-        'd' => Some(KeyCode::D),
-        'e' => Some(KeyCode::E),
-        'f' => Some(KeyCode::F),
-        'g' => Some(KeyCode::G),
-        'h' => Some(KeyCode::H),
-        'i' => Some(KeyCode::I),
-        'j' => Some(KeyCode::J),
-        'k' => Some(KeyCode::K),
-        'l' => Some(KeyCode::L),
-        'm' => Some(KeyCode::M),
-        'n' => Some(KeyCode::N),
-        'o' => Some(KeyCode::O),
-        'p' => Some(KeyCode::P),
-        'q' => Some(KeyCode::Q),
-        'r' => Some(KeyCode::R),
-        's' => Some(KeyCode::S),
-        't' => Some(KeyCode::T),
-        'u' => Some(KeyCode::U),
-        'v' => Some(KeyCode::V),
-        'w' => Some(KeyCode::W),
-        'x' => Some(KeyCode::X),
-        'y' => Some(KeyCode::Y),
-        'z' => Some(KeyCode::Z),
-        _ => None,
+/// A shape telling the sounds apart without reading, centered on `x`, `y` and `size` across.
+fn draw_icon(event: SoundEvent, x: f32, y: f32, size: f32, color: Color) {
+    let r = size / 2.0;
+    match event {
+        // The revolver's cylinder
+        SoundEvent::Cock => {
+            draw_circle_lines(x, y, r, 4.0, color);
+            for i in 0..6 {
+                let angle = i as f32 * std::f32::consts::TAU / 6.0;
+                draw_circle(
+                    x + angle.cos() * r * 0.55,
+                    y + angle.sin() * r * 0.55,
+                    r * 0.18,
+                    color,
+                );
+            }
+        }
+        // A burst
+        SoundEvent::Shot => {
+            for i in 0..8 {
+                let angle = i as f32 * std::f32::consts::TAU / 8.0;
+                let (dx, dy) = (angle.cos(), angle.sin());
+                draw_line(
+                    x + dx * r * 0.3,
+                    y + dy * r * 0.3,
+                    x + dx * r,
+                    y + dy * r,
+                    6.0,
+                    color,
+                );
+            }
+        }
+        // Crossed out
+        SoundEvent::Misfire => {
+            draw_circle_lines(x, y, r, 4.0, color);
+            draw_line(
+                x - r * 0.5,
+                y - r * 0.5,
+                x + r * 0.5,
+                y + r * 0.5,
+                6.0,
+                color,
+            );
+            draw_line(
+                x + r * 0.5,
+                y - r * 0.5,
+                x - r * 0.5,
+                y + r * 0.5,
+                6.0,
+                color,
+            );
+        }
+        // Pointing down at the keys
+        SoundEvent::TurnStart => draw_triangle(
+            vec2(x - r, y - r * 0.6),
+            vec2(x + r, y - r * 0.6),
+            vec2(x, y + r * 0.8),
+            color,
+        ),
+        // A star, as triangles from its middle to each point
+        SoundEvent::Win => {
+            let point = |i: f32, radius: f32| {
+                let angle = i * std::f32::consts::TAU / 10.0 - std::f32::consts::FRAC_PI_2;
+                vec2(x + angle.cos() * radius, y + angle.sin() * radius)
+            };
+            for i in (0..10).step_by(2).map(|i| i as f32) {
+                let tip = point(i, r);
+                draw_triangle(vec2(x, y), point(i - 1.0, r * 0.4), tip, color);
+                draw_triangle(vec2(x, y), tip, point(i + 1.0, r * 0.4), color);
+            }
+        }
     }
 }
 
+fn bracket_view(ui: &mut egui::Ui, bracket: &Bracket, username: &str) {
+    let mode = match bracket.mode {
+        BracketMode::Survivors => "Survivors advance",
+        BracketMode::Losers => "Loser's bracket",
+    };
+    ui.label(format!("{mode}, tables of {}", bracket.table_size));
+
+    ui.horizontal_top(|ui| {
+        for (round_idx, round) in bracket.rounds.iter().enumerate() {
+            ui.vertical(|ui| {
+                ui.strong(format!("Round {}", round_idx + 1));
+                for (table_idx, table) in round.iter().enumerate() {
+                    ui.group(|ui| {
+                        ui.label(format!("Table {}", table_idx + 1));
+                        for player in &table.players {
+                            let mut text = egui::RichText::new(player);
+                            if table.advancing.contains(player) {
+                                text = text.color(egui::Color32::GREEN);
+                            } else if table.out.contains(player) {
+                                text = text.color(egui::Color32::RED).strikethrough();
+                            }
+                            if player == username {
+                                text = text.strong();
+                            }
+                            ui.label(text);
+                        }
+                    });
+                }
+            });
+        }
+    });
+}
+
 fn centered_text_at(text: &str, x: f32, y: f32, size: f32, color: Color) {
     let dim = measure_text(text, None, size as u16, 1.0);
     draw_text(
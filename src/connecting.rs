@@ -0,0 +1,276 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::mpsc::{Receiver, TryRecvError},
+    time::{Duration, Instant},
+};
+
+use egui_macroquad::{
+    egui::{self, RichText},
+    macroquad::prelude::*,
+};
+use tokio::{net::TcpStream, sync::mpsc};
+
+use crate::{
+    c2s_packet::{C2sPacket, PROTOCOL_VERSION},
+    lobby::{JoinTarget, LobbyState},
+    main_menu::MainMenuState,
+    packet_channel::{self, DecodeError},
+    relay_protocol::{self, RelayHello, RelayReply},
+    s2c_packet::S2cPacket,
+    settings_scene, GameState,
+};
+use anyhow::Result;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const MAX_ATTEMPTS: u32 = 4;
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub enum ConnectError {
+    Dns(String),
+    Refused,
+    Timeout,
+    VersionMismatch,
+    /// The server or relay answered but turned us away.
+    Rejected(String),
+    Io(String),
+}
+
+impl ConnectError {
+    fn retryable(&self) -> bool {
+        matches!(self, Self::Refused | Self::Timeout | Self::Io(_))
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Dns(e) => write!(f, "Could not find the server ({e})"),
+            Self::Refused => write!(f, "The server refused the connection, is it running?"),
+            Self::Timeout => write!(f, "The server did not answer in time"),
+            Self::VersionMismatch => write!(f, "The server runs a different version of the game"),
+            Self::Rejected(msg) => write!(f, "{msg}"),
+            Self::Io(e) => write!(f, "Connection failed ({e})"),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConnectError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionRefused => Self::Refused,
+            std::io::ErrorKind::TimedOut => Self::Timeout,
+            _ => Self::Io(e.to_string()),
+        }
+    }
+}
+
+impl From<anyhow::Error> for ConnectError {
+    fn from(e: anyhow::Error) -> Self {
+        if e.is::<DecodeError>() {
+            return Self::VersionMismatch;
+        }
+        match e.downcast::<std::io::Error>() {
+            Ok(e) => e.into(),
+            Err(e) => Self::Io(e.to_string()),
+        }
+    }
+}
+
+/// Opens a stream to the server `target` points at, going through the relay when it has a code.
+pub async fn open(target: &JoinTarget) -> Result<TcpStream, ConnectError> {
+    let addrs = tokio::net::lookup_host(&target.addr)
+        .await
+        .map_err(|e| ConnectError::Dns(e.to_string()))?
+        .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        return Err(ConnectError::Dns(format!(
+            "no addresses for {}",
+            target.addr
+        )));
+    }
+    let mut stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addrs[..]))
+        .await
+        .map_err(|_| ConnectError::Timeout)??;
+
+    if let Some(code) = &target.relay {
+        relay_protocol::send(&mut stream, &RelayHello::Join(code.clone())).await?;
+        match relay_protocol::recv(&mut stream).await? {
+            RelayReply::Connected => {}
+            RelayReply::Error(e) => return Err(ConnectError::Rejected(e)),
+            _ => return Err(ConnectError::Io("unexpected reply from relay".to_string())),
+        }
+    }
+    Ok(stream)
+}
+
+/// A connection that got past the handshake, along with the first packet the server sent.
+struct Connection {
+    tx: mpsc::Sender<C2sPacket>,
+    rx: mpsc::Receiver<Result<S2cPacket>>,
+    server_addr: Option<SocketAddr>,
+    first: S2cPacket,
+}
+
+enum ConnectStage {
+    Connecting(Receiver<Result<Connection, ConnectError>>),
+    Retrying(Instant, ConnectError),
+    Failed(ConnectError),
+}
+
+pub struct ConnectingState {
+    username: String,
+    target: JoinTarget,
+    attempt: u32,
+    stage: ConnectStage,
+}
+
+impl ConnectingState {
+    pub fn new(username: &str, target: JoinTarget) -> Self {
+        Self {
+            stage: Self::spawn_attempt(username, &target),
+            username: username.to_string(),
+            target,
+            attempt: 1,
+        }
+    }
+
+    fn spawn_attempt(username: &str, target: &JoinTarget) -> ConnectStage {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let username = username.to_string();
+        let target = target.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(Self::connect(username, target).await);
+        });
+        ConnectStage::Connecting(rx)
+    }
+
+    async fn connect(username: String, target: JoinTarget) -> Result<Connection, ConnectError> {
+        let stream = open(&target).await?;
+        let server_addr = stream.peer_addr().ok().filter(|_| target.relay.is_none());
+
+        let (mut rx, tx) = packet_channel::async_channel(stream);
+        let _ = tx
+            .send(C2sPacket::CreatePlayer(PROTOCOL_VERSION, username))
+            .await;
+        if let Some(room) = target.room {
+            let _ = tx.send(C2sPacket::JoinRoom(room, target.password)).await;
+        }
+
+        // The server greets every new player, so no answer means something is wrong
+        let first = tokio::time::timeout(CONNECT_TIMEOUT, rx.recv())
+            .await
+            .map_err(|_| ConnectError::Timeout)?;
+        match first {
+            Some(Ok(S2cPacket::Disconnect(msg))) => Err(ConnectError::Rejected(msg)),
+            Some(Ok(first)) => Ok(Connection {
+                tx,
+                rx,
+                server_addr,
+                first,
+            }),
+            Some(Err(e)) => Err(e.into()),
+            None => Err(ConnectError::Io("connection closed".to_string())),
+        }
+    }
+
+    fn retry_delay(&self) -> Duration {
+        FIRST_RETRY_DELAY * 2u32.pow(self.attempt - 1)
+    }
+
+    pub fn tick(mut self) -> GameState {
+        if let ConnectStage::Connecting(rx) = &self.stage {
+            match rx.try_recv() {
+                Ok(Ok(conn)) => {
+                    return LobbyState::connected(
+                        &self.username,
+                        self.target,
+                        conn.tx,
+                        conn.rx,
+                        conn.server_addr,
+                        conn.first,
+                    );
+                }
+                Ok(Err(e)) if e.retryable() && self.attempt < MAX_ATTEMPTS => {
+                    self.stage = ConnectStage::Retrying(Instant::now() + self.retry_delay(), e);
+                }
+                Ok(Err(e)) => self.stage = ConnectStage::Failed(e),
+                Err(TryRecvError::Disconnected) => {
+                    self.stage = ConnectStage::Failed(ConnectError::Io(
+                        "the connection attempt crashed".to_string(),
+                    ));
+                }
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        if let ConnectStage::Retrying(at, _) = &self.stage {
+            if Instant::now() >= *at {
+                self.attempt += 1;
+                self.stage = Self::spawn_attempt(&self.username, &self.target);
+            }
+        }
+
+        let mut cancel = false;
+        let mut retry = false;
+        egui_macroquad::ui(|ctx| {
+            egui::CentralPanel::default()
+                .frame(egui::Frame::dark_canvas(&ctx.style()))
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.label(RichText::new("Bluescreen Roulette").size(32.0));
+                    });
+                    let window_pos_x = (screen_width() - 200.0) / 2.0;
+                    let window_pos_y = (screen_height() - 200.0) / 2.0;
+
+                    egui::Window::new("Connecting")
+                        .fixed_pos((window_pos_x, window_pos_y))
+                        .fixed_size((200.0, 200.0))
+                        .collapsible(false)
+                        .resizable(false)
+                        .show(ctx, |ui| {
+                            ui.label(format!("Server: {}", self.target.addr));
+                            match &self.stage {
+                                ConnectStage::Connecting(_) => {
+                                    ui.horizontal(|ui| {
+                                        ui.spinner();
+                                        ui.label(format!(
+                                            "Attempt {} of {MAX_ATTEMPTS}",
+                                            self.attempt
+                                        ));
+                                    });
+                                }
+                                ConnectStage::Retrying(at, e) => {
+                                    ui.colored_label(egui::Color32::RED, e.to_string());
+                                    let left = at.saturating_duration_since(Instant::now());
+                                    ui.label(format!(
+                                        "Retrying in {}s...",
+                                        left.as_secs_f32().ceil()
+                                    ));
+                                }
+                                ConnectStage::Failed(e) => {
+                                    ui.colored_label(egui::Color32::RED, e.to_string());
+                                }
+                            }
+                            ui.horizontal(|ui| {
+                                cancel = ui.button("Cancel").clicked();
+                                if !matches!(self.stage, ConnectStage::Connecting(_)) {
+                                    retry = ui.button("Retry now").clicked();
+                                }
+                            });
+                        });
+                });
+            settings_scene::overlay(ctx);
+        });
+        egui_macroquad::draw();
+
+        if cancel {
+            return GameState::MainMenu(MainMenuState::new());
+        }
+        if retry {
+            self.attempt = 1;
+            self.stage = Self::spawn_attempt(&self.username, &self.target);
+        }
+        GameState::Connecting(self)
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    net::SocketAddr,
+    sync::{mpsc::Receiver, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+pub const DISCOVERY_PORT: u16 = 1235;
+const MAGIC: &[u8; 4] = b"BSR1";
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+const EXPIRE_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerAnnouncement {
+    pub name: String,
+    pub players: usize,
+    pub port: u16,
+    pub in_game: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredServer {
+    pub addr: SocketAddr,
+    pub announcement: ServerAnnouncement,
+    last_seen: Instant,
+}
+
+/// Broadcasts the current state of a server on the LAN until `token` is cancelled.
+pub async fn announce(announcement: Arc<Mutex<ServerAnnouncement>>, token: CancellationToken) {
+    let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await else {
+        eprintln!("[Discovery] could not bind announcement socket");
+        return;
+    };
+    if let Err(e) = socket.set_broadcast(true) {
+        eprintln!("[Discovery] could not enable broadcast: {e}");
+        return;
+    }
+
+    loop {
+        let mut data = MAGIC.to_vec();
+        data.extend(bincode::serialize(&*announcement.lock().unwrap()).unwrap());
+        let _ = socket
+            .send_to(&data, ("255.255.255.255", DISCOVERY_PORT))
+            .await;
+
+        tokio::select! {
+            _ = tokio::time::sleep(ANNOUNCE_INTERVAL) => {}
+            _ = token.cancelled() => break,
+        }
+    }
+}
+
+/// Binds the discovery port so that other clients on this machine can listen on it too.
+fn bind_shared(port: u16) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    // Linux shares UDP ports with address reuse alone, the BSDs and macOS also need this
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&SocketAddr::from(([0, 0, 0, 0], port)).into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+pub struct LanDiscovery {
+    rx: Receiver<(SocketAddr, ServerAnnouncement)>,
+    servers: Vec<DiscoveredServer>,
+    token: CancellationToken,
+    /// Why discovery isn't running, if it isn't.
+    pub error: Option<String>,
+}
+
+impl LanDiscovery {
+    pub fn start() -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let token = CancellationToken::new();
+        let closed = token.clone();
+        let socket = match bind_shared(DISCOVERY_PORT) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("[Discovery] could not listen on port {DISCOVERY_PORT}: {e}");
+                return Self {
+                    rx,
+                    servers: vec![],
+                    token,
+                    error: Some(format!("Could not listen on port {DISCOVERY_PORT}: {e}")),
+                };
+            }
+        };
+        tokio::spawn(async move {
+            let mut data = vec![0; 1024];
+            loop {
+                let (len, from) = tokio::select! {
+                    res = socket.recv_from(&mut data) => {
+                        let Ok(res) = res else {
+                            continue;
+                        };
+                        res
+                    }
+                    _ = closed.cancelled() => break,
+                };
+
+                let Some(payload) = data[..len].strip_prefix(MAGIC) else {
+                    continue;
+                };
+                let Ok(announcement) = bincode::deserialize::<ServerAnnouncement>(payload) else {
+                    continue;
+                };
+                if tx.send((from, announcement)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            rx,
+            servers: vec![],
+            token,
+            error: None,
+        }
+    }
+
+    pub fn servers(&mut self) -> &[DiscoveredServer] {
+        while let Ok((from, announcement)) = self.rx.try_recv() {
+            let addr = SocketAddr::new(from.ip(), announcement.port);
+            if let Some(server) = self.servers.iter_mut().find(|s| s.addr == addr) {
+                server.announcement = announcement;
+                server.last_seen = Instant::now();
+            } else {
+                self.servers.push(DiscoveredServer {
+                    addr,
+                    announcement,
+                    last_seen: Instant::now(),
+                });
+            }
+        }
+
+        self.servers
+            .retain(|s| s.last_seen.elapsed() < EXPIRE_AFTER);
+        &self.servers
+    }
+}
+
+impl Drop for LanDiscovery {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
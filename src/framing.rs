@@ -0,0 +1,24 @@
+use anyhow::{anyhow, Result};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const MAX_FRAME_SIZE: u32 = 1024 * 1024;
+
+/// Reads one length-prefixed frame off the stream.
+pub async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>> {
+    let Ok(len) = stream.read_u32_le().await else {
+        return Err(anyhow!("EOF"));
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(anyhow!("packet too large ({len} bytes)"));
+    }
+
+    let mut data = vec![0; len as usize];
+    stream.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+pub async fn write_frame(stream: &mut (impl AsyncWrite + Unpin), data: &[u8]) -> Result<()> {
+    stream.write_u32_le(data.len() as u32).await?;
+    stream.write_all(data).await?;
+    Ok(())
+}
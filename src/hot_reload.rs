@@ -0,0 +1,104 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use lazy_static::lazy_static;
+
+/// Read relative to the working directory, so run from the repository root.
+const DIR: &str = "./bundle";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Files changed since the last `take_changes`, `None` if deleted.
+    static ref CHANGES: Mutex<Vec<(String, Option<Vec<u8>>)>> = Mutex::new(vec![]);
+}
+
+/// Reads the bundle from `./bundle` instead of the embedded archive and watches it for changes.
+/// Only debug builds have this, and it has to happen before anything touches `BUNDLE`.
+pub fn enable() {
+    if !cfg!(debug_assertions) {
+        eprintln!("[Dev] --dev only works in debug builds, using the embedded bundle");
+        return;
+    }
+    if !Path::new(DIR).is_dir() {
+        eprintln!("[Dev] {DIR} not found, using the embedded bundle");
+        return;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    std::thread::spawn(watch);
+    println!("[Dev] loading assets from {DIR}");
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn read_bundle() -> HashMap<String, Vec<u8>> {
+    snapshot()
+        .into_keys()
+        .filter_map(|path| Some((path.clone(), read(&path)?)))
+        .collect()
+}
+
+pub fn take_changes() -> Vec<(String, Option<Vec<u8>>)> {
+    std::mem::take(&mut CHANGES.lock().unwrap())
+}
+
+fn read(bundle_path: &str) -> Option<Vec<u8>> {
+    std::fs::read(format!("{DIR}{bundle_path}")).ok()
+}
+
+/// Modification time and size of every file, keyed by bundle path.
+fn snapshot() -> HashMap<String, (SystemTime, u64)> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![String::new()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(format!("{DIR}{dir}")) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = format!("{dir}/{}", entry.file_name().to_string_lossy());
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            if meta.is_dir() {
+                dirs.push(path);
+            } else {
+                let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                files.insert(path, (modified, meta.len()));
+            }
+        }
+    }
+    files
+}
+
+fn watch() {
+    let mut last = snapshot();
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let current = snapshot();
+        let mut changes = vec![];
+        for (path, stamp) in &current {
+            if last.get(path) != Some(stamp) {
+                println!("[Dev] {path} changed");
+                changes.push((path.clone(), read(path)));
+            }
+        }
+        for path in last.keys().filter(|path| !current.contains_key(*path)) {
+            println!("[Dev] {path} removed");
+            changes.push((path.clone(), None));
+        }
+        if !changes.is_empty() {
+            CHANGES.lock().unwrap().extend(changes);
+        }
+        last = current;
+    }
+}
@@ -0,0 +1,325 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use egui_macroquad::egui;
+use qrcode::{Color, QrCode};
+
+use crate::lobby::JoinTarget;
+
+/// Crockford's base32 alphabet, which leaves out letters easily mistaken for digits.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const VERSION: u8 = 1;
+/// Stands in for the address family when the invite goes through a relay.
+const RELAY: u8 = 0;
+const GROUP_LEN: usize = 4;
+const QR_MODULE_SIZE: f32 = 4.0;
+const QR_QUIET_ZONE: usize = 2;
+/// Links are this followed by an invite code, so the OS can hand them to the game.
+pub const URL_SCHEME: &str = "bluescreen-roulette://";
+
+/// How the invited player reaches the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Route {
+    Direct(SocketAddr),
+    Relay { relay: String, code: String },
+}
+
+/// Everything needed to join a room, packed into a short code people can read out or paste.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invite {
+    pub route: Route,
+    pub room: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Invite {
+    pub fn encode(&self) -> String {
+        let mut data = vec![VERSION];
+        match &self.route {
+            Route::Direct(addr) => {
+                match addr.ip() {
+                    IpAddr::V4(ip) => {
+                        data.push(4);
+                        data.extend(ip.octets());
+                    }
+                    IpAddr::V6(ip) => {
+                        data.push(6);
+                        data.extend(ip.octets());
+                    }
+                }
+                data.extend(addr.port().to_be_bytes());
+            }
+            Route::Relay { relay, code } => {
+                data.push(RELAY);
+                push_text(&mut data, relay);
+                push_text(&mut data, code);
+            }
+        }
+        for text in [&self.room, &self.password] {
+            push_text(&mut data, text.as_deref().unwrap_or_default());
+        }
+        data.extend(checksum(&data).to_be_bytes());
+
+        to_base32(&data)
+            .as_bytes()
+            .chunks(GROUP_LEN)
+            .map(|group| std::str::from_utf8(group).unwrap())
+            .collect::<Vec<_>>()
+            .join("-")
+    }
+
+    /// Returns `None` for anything that isn't a valid invite, so callers can fall back to treating it as an address.
+    pub fn decode(code: &str) -> Option<Self> {
+        let data = from_base32(code)?;
+        let (body, sum) = data.split_at(data.len().checked_sub(2)?);
+        if checksum(body).to_be_bytes() != sum {
+            return None;
+        }
+
+        let mut reader = body.iter().copied();
+        if reader.next()? != VERSION {
+            return None;
+        }
+        let ip = match reader.next()? {
+            4 => Some(IpAddr::V4(Ipv4Addr::from(
+                <[u8; 4]>::try_from(take(&mut reader, 4)?).ok()?,
+            ))),
+            6 => Some(IpAddr::V6(Ipv6Addr::from(
+                <[u8; 16]>::try_from(take(&mut reader, 16)?).ok()?,
+            ))),
+            RELAY => None,
+            _ => return None,
+        };
+        let route = match ip {
+            Some(ip) => {
+                let port = u16::from_be_bytes([reader.next()?, reader.next()?]);
+                Route::Direct(SocketAddr::new(ip, port))
+            }
+            None => Route::Relay {
+                relay: take_text(&mut reader)?,
+                code: take_text(&mut reader)?,
+            },
+        };
+        let room = Some(take_text(&mut reader)?).filter(|r| !r.is_empty());
+        let password = Some(take_text(&mut reader)?).filter(|p| !p.is_empty());
+
+        Some(Self {
+            route,
+            room,
+            password,
+        })
+    }
+
+    /// The invite as a `bluescreen-roulette://` link, which joins like the bare code does.
+    pub fn link(&self) -> String {
+        format!("{URL_SCHEME}{}", self.encode())
+    }
+
+    pub fn from_link(link: &str) -> Option<Self> {
+        let code = link.trim().strip_prefix(URL_SCHEME)?;
+        Self::decode(code.trim_end_matches('/'))
+    }
+
+    pub fn target(self) -> JoinTarget {
+        let (addr, relay) = match self.route {
+            Route::Direct(addr) => (addr.to_string(), None),
+            Route::Relay { relay, code } => (relay, Some(code)),
+        };
+        JoinTarget {
+            addr,
+            room: self.room,
+            password: self.password,
+            relay,
+        }
+    }
+}
+
+fn take(reader: &mut impl Iterator<Item = u8>, len: usize) -> Option<Vec<u8>> {
+    let bytes = reader.take(len).collect::<Vec<_>>();
+    (bytes.len() == len).then_some(bytes)
+}
+
+/// Reads a length-prefixed string written by `push_text`.
+fn take_text(reader: &mut impl Iterator<Item = u8>) -> Option<String> {
+    let len = reader.next()? as usize;
+    String::from_utf8(take(reader, len)?).ok()
+}
+
+/// Writes `text` behind its length, cut to the last whole character that fits in 255 bytes.
+fn push_text(data: &mut Vec<u8>, text: &str) {
+    let mut len = text.len().min(u8::MAX as usize);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    data.push(len as u8);
+    data.extend(&text.as_bytes()[..len]);
+}
+
+/// Fletcher-16, enough to catch typos.
+fn checksum(data: &[u8]) -> u16 {
+    let (mut a, mut b) = (0u16, 0u16);
+    for byte in data {
+        a = (a + *byte as u16) % 255;
+        b = (b + a) % 255;
+    }
+    (b << 8) | a
+}
+
+fn to_base32(data: &[u8]) -> String {
+    let mut out = String::new();
+    let (mut buffer, mut bits) = (0u32, 0);
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(ALPHABET[(buffer >> bits) as usize & 31] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    out
+}
+
+fn from_base32(code: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in code.chars().filter(|c| !matches!(c, '-' | ' ')) {
+        let c = match c.to_ascii_uppercase() {
+            'O' => '0',
+            'I' | 'L' => '1',
+            c => c,
+        };
+        let value = ALPHABET.iter().position(|a| *a as char == c)? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Draws `text` as a QR code, black on white so phones can scan it off a dark theme.
+pub fn qr_code(ui: &mut egui::Ui, text: &str) {
+    let Ok(code) = QrCode::new(text) else {
+        return;
+    };
+    let width = code.width();
+    let side = (width + QR_QUIET_ZONE * 2) as f32 * QR_MODULE_SIZE;
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(side, side), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 0.0, egui::Color32::WHITE);
+
+    for (i, color) in code.to_colors().into_iter().enumerate() {
+        if color != Color::Dark {
+            continue;
+        }
+        let x = (i % width + QR_QUIET_ZONE) as f32 * QR_MODULE_SIZE;
+        let y = (i / width + QR_QUIET_ZONE) as f32 * QR_MODULE_SIZE;
+        let module = egui::Rect::from_min_size(
+            rect.min + egui::vec2(x, y),
+            egui::vec2(QR_MODULE_SIZE, QR_MODULE_SIZE),
+        );
+        painter.rect_filled(module, 0.0, egui::Color32::BLACK);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn direct() -> Invite {
+        Invite {
+            route: Route::Direct("192.168.1.20:1234".parse().unwrap()),
+            room: Some("ABCDE".to_string()),
+            password: Some("hunter2".to_string()),
+        }
+    }
+
+    #[test]
+    fn direct_invites_round_trip() {
+        for invite in [
+            direct(),
+            Invite {
+                route: Route::Direct("[2001:db8::1]:4000".parse().unwrap()),
+                room: None,
+                password: None,
+            },
+        ] {
+            assert_eq!(Invite::decode(&invite.encode()), Some(invite));
+        }
+    }
+
+    #[test]
+    fn relay_invites_round_trip() {
+        let invite = Invite {
+            route: Route::Relay {
+                relay: "relay.example.com:1236".to_string(),
+                code: "K7QX2M".to_string(),
+            },
+            room: Some("ABCDE".to_string()),
+            password: None,
+        };
+        assert_eq!(Invite::decode(&invite.encode()), Some(invite));
+    }
+
+    #[test]
+    fn codes_survive_lowercase_and_lookalike_letters() {
+        let code = direct().encode();
+        let retyped = code.to_lowercase().replace('0', "o").replace('1', "l");
+        assert_eq!(Invite::decode(&retyped), Some(direct()));
+    }
+
+    #[test]
+    fn long_texts_are_cut_at_a_character_boundary() {
+        let mut invite = direct();
+        invite.room = Some("é".repeat(200));
+        let decoded = Invite::decode(&invite.encode()).unwrap();
+        assert_eq!(decoded.room, Some("é".repeat(127)));
+    }
+
+    #[test]
+    fn links_wrap_the_code() {
+        let link = direct().link();
+        assert_eq!(link, format!("{URL_SCHEME}{}", direct().encode()));
+        assert_eq!(Invite::from_link(&link), Some(direct()));
+        // Some apps add a trailing slash when opening links
+        assert_eq!(Invite::from_link(&format!("{link}/")), Some(direct()));
+        assert_eq!(Invite::from_link(&direct().encode()), None);
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        for data in [&b""[..], b"\x00", b"\xff\x01", b"bluescreen roulette"] {
+            assert_eq!(from_base32(&to_base32(data)).unwrap(), data);
+        }
+        assert_eq!(to_base32(b"\xff\xff"), "ZZZG");
+        assert_eq!(from_base32("Z"), Some(vec![]));
+        assert_eq!(from_base32("not base32!"), None);
+    }
+
+    #[test]
+    fn corrupted_codes_are_rejected() {
+        let data = from_base32(&direct().encode()).unwrap();
+        // A typo in the body, and one in the checksum itself
+        for i in [3, data.len() - 1] {
+            let mut corrupted = data.clone();
+            corrupted[i] ^= 0x10;
+            assert_eq!(Invite::decode(&to_base32(&corrupted)), None, "byte {i}");
+        }
+    }
+
+    #[test]
+    fn truncated_codes_are_rejected() {
+        let data = from_base32(&direct().encode()).unwrap();
+        for len in 0..data.len() {
+            assert_eq!(
+                Invite::decode(&to_base32(&data[..len])),
+                None,
+                "{len} bytes"
+            );
+        }
+    }
+}
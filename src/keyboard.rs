@@ -0,0 +1,216 @@
+use egui_macroquad::macroquad::input::KeyCode;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+/// The physical keys the grid is drawn on, by row, as US key codes.
+const SLOTS: [[KeyCode; 10]; 3] = [
+    [
+        KeyCode::Q,
+        KeyCode::W,
+        KeyCode::E,
+        KeyCode::R,
+        KeyCode::T,
+        KeyCode::Y,
+        KeyCode::U,
+        KeyCode::I,
+        KeyCode::O,
+        KeyCode::P,
+    ],
+    [
+        KeyCode::A,
+        KeyCode::S,
+        KeyCode::D,
+        KeyCode::F,
+        KeyCode::G,
+        KeyCode::H,
+        KeyCode::J,
+        KeyCode::K,
+        KeyCode::L,
+        KeyCode::Semicolon,
+    ],
+    [
+        KeyCode::Z,
+        KeyCode::X,
+        KeyCode::C,
+        KeyCode::V,
+        KeyCode::B,
+        KeyCode::N,
+        KeyCode::M,
+        KeyCode::Comma,
+        KeyCode::Period,
+        KeyCode::Slash,
+    ],
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KeyboardLayout {
+    /// Whatever the system uses, see `detect`.
+    Auto,
+    Qwerty,
+    Azerty,
+    Qwertz,
+    Dvorak,
+}
+
+lazy_static! {
+    static ref DETECTED: KeyboardLayout = KeyboardLayout::detect();
+}
+
+impl KeyboardLayout {
+    pub const ALL: &'static [Self] = &[
+        Self::Auto,
+        Self::Qwerty,
+        Self::Azerty,
+        Self::Qwertz,
+        Self::Dvorak,
+    ];
+
+    /// The layout to actually use, with `Auto` replaced by the detected one.
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Auto => *DETECTED,
+            layout => layout,
+        }
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Self::Auto => format!("Automatic: {}", DETECTED.name()),
+            Self::Qwerty => "QWERTY".to_string(),
+            Self::Azerty => "AZERTY".to_string(),
+            Self::Qwertz => "QWERTZ".to_string(),
+            Self::Dvorak => "Dvorak".to_string(),
+        }
+    }
+
+    /// The letter printed on each slot of `SLOTS`, a space where there is none.
+    pub fn rows(self) -> [&'static str; 3] {
+        match self.resolve() {
+            Self::Auto | Self::Qwerty => ["QWERTYUIOP", "ASDFGHJKL ", "ZXCVBNM   "],
+            Self::Azerty => ["AZERTYUIOP", "QSDFGHJKLM", "WXCVBN    "],
+            Self::Qwertz => ["QWERTZUIOP", "ASDFGHJKL ", "YXCVBNM   "],
+            Self::Dvorak => ["   PYFGCRL", "AOEUIDHTNS", " QJKXBMWVZ"],
+        }
+    }
+
+    /// The key code macroquad reports when the key labelled `letter` is pressed.
+    pub fn keycode(self, letter: char) -> Option<KeyCode> {
+        if cfg!(target_os = "linux") {
+            // X11 key codes already follow the active layout
+            return letter_keycode(letter);
+        }
+        // Elsewhere they name the physical key by its US position
+        let letter = letter.to_ascii_uppercase();
+        self.rows()
+            .iter()
+            .zip(SLOTS)
+            .find_map(|(row, slots)| Some(slots[row.find(letter)?]))
+    }
+
+    /// Asks Windows for the active layout, elsewhere guesses from the X keyboard settings on
+    /// Linux, then from the locale.
+    fn detect() -> Self {
+        #[cfg(windows)]
+        {
+            // SAFETY: takes no pointers, thread 0 means the calling thread
+            let hkl = unsafe { GetKeyboardLayout(0) } as usize;
+            return Self::for_hkl(hkl as u32);
+        }
+        #[allow(unreachable_code)]
+        let xkb = std::process::Command::new("setxkbmap")
+            .arg("-query")
+            .output()
+            .ok()
+            .map(|out| String::from_utf8_lossy(&out.stdout).to_string())
+            .unwrap_or_default();
+        let value = |key: &str| {
+            xkb.lines()
+                .find_map(|line| line.strip_prefix(key))
+                .map(|v| v.trim().split(',').next().unwrap_or_default().to_string())
+        };
+        if value("variant:").is_some_and(|v| v.contains("dvorak")) {
+            return Self::Dvorak;
+        }
+        if let Some(layout) = value("layout:") {
+            return Self::for_code(&layout);
+        }
+
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+            .unwrap_or_default();
+        // Locales look like fr_BE.UTF-8, where the country decides the layout
+        let country = locale
+            .split(['_', '.'])
+            .nth(1)
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        Self::for_code(&country)
+    }
+
+    /// The low word of a layout handle is the language, the high word the layout when it
+    /// differs from the language's default, like 0xF002 for US Dvorak.
+    #[cfg_attr(not(windows), allow(dead_code))]
+    fn for_hkl(hkl: u32) -> Self {
+        let language = hkl & 0xFFFF;
+        if hkl >> 16 == 0xF002 {
+            return Self::Dvorak;
+        }
+        match (language & 0x3FF, language >> 10) {
+            // French, except Canadian (3) and Swiss (4) French
+            (0x0C, sub) if sub != 3 && sub != 4 => Self::Azerty,
+            (0x0C, 4) => Self::Qwertz,
+            // Belgian Dutch
+            (0x13, 2) => Self::Azerty,
+            // German, Czech, Slovak, Hungarian, Slovenian, Croatian
+            (0x07 | 0x05 | 0x1B | 0x0E | 0x24 | 0x1A, _) => Self::Qwertz,
+            _ => Self::Qwerty,
+        }
+    }
+
+    fn for_code(code: &str) -> Self {
+        match code {
+            "fr" | "be" => Self::Azerty,
+            "de" | "at" | "ch" | "cz" | "sk" | "hu" | "si" | "hr" => Self::Qwertz,
+            _ => Self::Qwerty,
+        }
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "user32")]
+extern "system" {
+    fn GetKeyboardLayout(thread: u32) -> *mut std::ffi::c_void;
+}
+
+fn letter_keycode(letter: char) -> Option<KeyCode> {
+    match letter.to_ascii_lowercase() {
+        'a' => Some(KeyCode::A),
+        'b' => Some(KeyCode::B),
+        'c' => Some(KeyCode::C),
+        'd' => Some(KeyCode::D),
+        'e' => Some(KeyCode::E),
+        'f' => Some(KeyCode::F),
+        'g' => Some(KeyCode::G),
+        'h' => Some(KeyCode::H),
+        'i' => Some(KeyCode::I),
+        'j' => Some(KeyCode::J),
+        'k' => Some(KeyCode::K),
+        'l' => Some(KeyCode::L),
+        'm' => Some(KeyCode::M),
+        'n' => Some(KeyCode::N),
+        'o' => Some(KeyCode::O),
+        'p' => Some(KeyCode::P),
+        'q' => Some(KeyCode::Q),
+        'r' => Some(KeyCode::R),
+        's' => Some(KeyCode::S),
+        't' => Some(KeyCode::T),
+        'u' => Some(KeyCode::U),
+        'v' => Some(KeyCode::V),
+        'w' => Some(KeyCode::W),
+        'x' => Some(KeyCode::X),
+        'y' => Some(KeyCode::Y),
+        'z' => Some(KeyCode::Z),
+        _ => None,
+    }
+}
@@ -3,7 +3,7 @@ use egui_macroquad::{
     macroquad::prelude::*,
 };
 
-use crate::{main_menu::MainMenuState, GameState};
+use crate::{main_menu::MainMenuState, settings_scene, GameState};
 
 pub struct KickedState {
     pub message: String,
@@ -35,6 +35,7 @@ impl KickedState {
                             });
                     });
                 });
+            settings_scene::overlay(ctx);
         });
         egui_macroquad::draw();
 
@@ -1,15 +1,28 @@
+use std::net::{IpAddr, SocketAddr};
+
 use egui_macroquad::{
     egui::{self, RichText},
     macroquad::prelude::*,
 };
-use tokio::{
-    net::TcpStream,
-    sync::mpsc::{Receiver, Sender},
-};
+use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::{
-    c2s_packet::C2sPacket, client_game::ClientGameState, kicked::KickedState,
-    main_menu::MainMenuState, packet_channel, s2c_packet::S2cPacket, GameState,
+    c2s_packet::C2sPacket,
+    client_game::ClientGameState,
+    connecting::ConnectingState,
+    invite::{self, Invite, Route},
+    kicked::KickedState,
+    main_menu::MainMenuState,
+    network_info,
+    port_mapping::{self, MappingStatus},
+    public_addr::{self, Lookup},
+    recent_servers::RecentServers,
+    room_browser,
+    s2c_packet::S2cPacket,
+    server::{RoomInfo, RoomSettings, ServerInfo, PORT},
+    settings_scene, sound_pack,
+    tournament::{Bracket, BracketMode},
+    GameState,
 };
 use anyhow::Result;
 
@@ -19,46 +32,233 @@ pub struct LobbyState {
     username: String,
     players: Vec<String>,
     host: bool,
+    bracket: Option<Bracket>,
+    table_size: usize,
+    bracket_mode: BracketMode,
+    rooms: Vec<RoomInfo>,
+    room: Option<RoomInfo>,
+    room_name_edit: String,
+    max_players_edit: usize,
+    password_edit: String,
+    /// Put the room password in the invite, which anyone who sees the code can read.
+    invite_password: bool,
+    code_edit: String,
+    room_error: Option<String>,
+    server_info: Option<ServerInfo>,
+    /// Address of the server, or `None` when connected through a relay.
+    server_addr: Option<SocketAddr>,
+    target: JoinTarget,
+    /// Addresses a server hosted on this machine can be reached at, for the host to invite with.
+    lan_addrs: Vec<(String, IpAddr)>,
+    lan_invite_ip: Option<IpAddr>,
+}
+
+/// Where to connect to, and optionally which room to join straight away.
+#[derive(Debug, Clone)]
+pub struct JoinTarget {
+    pub addr: String,
+    pub room: Option<String>,
+    pub password: Option<String>,
+    /// Relay code of the server when `addr` points at a relay.
+    pub relay: Option<String>,
+}
+
+impl JoinTarget {
+    pub fn server(addr: &str) -> Self {
+        Self {
+            addr: addr.to_string(),
+            room: None,
+            password: None,
+            relay: None,
+        }
+    }
+
+    /// Accepts an invite link or code, a `bluescreen-roulette://host:port/room` link or a plain
+    /// address, using the default port when none is given.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if let Some(rest) = value.strip_prefix(invite::URL_SCHEME) {
+            if let Some(invite) = Invite::from_link(value) {
+                return Some(invite.target());
+            }
+            let (addr, room) = match rest.split_once('/') {
+                Some((addr, room)) => (addr, Some(room.trim_end_matches('/'))),
+                None => (rest, None),
+            };
+            let mut target = Self::server(&addr_or_default_port(addr));
+            target.room = room.filter(|r| !r.is_empty()).map(str::to_string);
+            return (!addr.is_empty()).then_some(target);
+        }
+        if let Some(invite) = Invite::decode(value) {
+            return Some(invite.target());
+        }
+        (!value.is_empty()).then(|| Self::server(&addr_or_default_port(value)))
+    }
+}
+
+fn port_mapped() -> bool {
+    matches!(
+        *port_mapping::STATUS.lock().unwrap(),
+        MappingStatus::Mapped { .. }
+    )
+}
+
+fn addr_or_default_port(addr: &str) -> String {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return addr.to_string();
+    }
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return SocketAddr::new(ip, PORT).to_string();
+    }
+    match addr.rsplit_once(':') {
+        Some((_, port)) if port.parse::<u16>().is_ok() => addr.to_string(),
+        _ => format!("{addr}:{PORT}"),
+    }
 }
 
 impl LobbyState {
-    pub fn try_new(username: &str, ip: &str) -> GameState {
-        let ip = ip.to_string();
-        let username = username.to_string();
-        let (tx, rx) = std::sync::mpsc::channel();
-        tokio::spawn(async move {
-            match tokio::time::timeout(tokio::time::Duration::from_secs(3), TcpStream::connect(ip))
-                .await
-            {
-                Ok(Ok(stream)) => {
-                    let (nrx, ntx) = packet_channel::async_channel(stream);
-                    ntx.send(C2sPacket::CreatePlayer(username.to_string()))
-                        .await
-                        .unwrap();
-                    tx.send(GameState::Lobby(Self {
-                        tx: ntx,
-                        rx: nrx,
-                        players: vec![],
-                        username: username.to_string(),
-                        host: false,
-                    }))
-                    .unwrap();
-                }
-                Err(e) => {
-                    println!("Error while connecting to server: {e}");
-                    tx.send(GameState::MainMenu(MainMenuState::new())).unwrap();
-                }
-                Ok(Err(e)) => {
-                    println!("Error while connecting to server: {e}");
-                    tx.send(GameState::MainMenu(MainMenuState::new())).unwrap();
+    /// Connects in the background, showing progress and errors until the lobby is reached.
+    pub fn try_new(username: &str, target: JoinTarget) -> GameState {
+        GameState::Connecting(ConnectingState::new(username, target))
+    }
+
+    /// Takes over a connection that finished its handshake, handling the packet that proved it.
+    pub fn connected(
+        username: &str,
+        target: JoinTarget,
+        tx: Sender<C2sPacket>,
+        rx: Receiver<Result<S2cPacket>>,
+        server_addr: Option<SocketAddr>,
+        first: S2cPacket,
+    ) -> GameState {
+        let lan_addrs = match server_addr {
+            Some(addr) if addr.ip().is_loopback() => network_info::server_addrs(),
+            _ => vec![],
+        };
+        // With a single interface there is nothing to choose, otherwise the host has to pick
+        let lan_invite_ip = match &lan_addrs[..] {
+            [(_, ip)] => Some(*ip),
+            _ => None,
+        };
+        let lobby = Self {
+            tx,
+            rx,
+            players: vec![],
+            username: username.to_string(),
+            host: false,
+            bracket: None,
+            table_size: 4,
+            bracket_mode: BracketMode::Survivors,
+            rooms: vec![],
+            room: None,
+            room_name_edit: String::new(),
+            max_players_edit: 8,
+            password_edit: String::new(),
+            invite_password: false,
+            code_edit: String::new(),
+            room_error: None,
+            server_info: None,
+            server_addr,
+            target,
+            lan_addrs,
+            lan_invite_ip,
+        };
+        match lobby.handle_packet(first) {
+            (_, Some(state)) => state,
+            (lobby, None) => GameState::Lobby(lobby.unwrap()),
+        }
+    }
+
+    /// Invite to the current room, through the relay when the server has one. A server on this
+    /// machine is shared through the public address if its port is forwarded, else through the
+    /// interface the host picked.
+    fn invite(&self, room: &RoomInfo) -> Option<Invite> {
+        let info = self.server_info.as_ref();
+        let route = if let (Some(relay), Some(code)) = (
+            info.and_then(|i| i.relay_addr.clone()),
+            info.and_then(|i| i.relay_code.clone()),
+        ) {
+            Route::Relay { relay, code }
+        } else if let Some(code) = &self.target.relay {
+            Route::Relay {
+                relay: self.target.addr.clone(),
+                code: code.clone(),
+            }
+        } else {
+            let mut addr = self.server_addr?;
+            if addr.ip().is_loopback() {
+                let status = port_mapping::STATUS.lock().unwrap().clone();
+                match (public_addr::lookup(), status) {
+                    (Lookup::Done(public), MappingStatus::Mapped { external_port, .. }) => {
+                        addr = SocketAddr::new(public.ip, external_port);
+                    }
+                    _ => addr.set_ip(self.lan_invite_ip?),
                 }
             }
-        });
-        rx.recv().unwrap()
+            Route::Direct(addr)
+        };
+        Some(Invite {
+            route,
+            room: Some(room.code.clone()),
+            password: Some(self.password_edit.clone())
+                .filter(|p| self.invite_password && room.locked && !p.is_empty()),
+        })
+    }
+
+    /// How forwarding the port of a server on this machine is going, which decides whether
+    /// the invite works outside the LAN.
+    fn port_forwarding(&self) -> Option<String> {
+        let addr = self.server_addr?;
+        if !self.host || !addr.ip().is_loopback() || self.target.relay.is_some() {
+            return None;
+        }
+        let port = addr.port();
+        Some(match port_mapping::STATUS.lock().unwrap().clone() {
+            MappingStatus::Off => format!("Port {port} is not forwarded"),
+            MappingStatus::Pending => format!("Forwarding port {port}..."),
+            MappingStatus::Mapped { description, .. } => {
+                format!("Invite works outside the LAN, {description}")
+            }
+            MappingStatus::Failed(e) => format!("Port forwarding failed: {e}"),
+        })
+    }
+
+    /// Whether the host has to pick which of their addresses goes in the invite.
+    fn picks_lan_address(&self) -> bool {
+        self.lan_addrs.len() > 1
+            && self.target.relay.is_none()
+            && self
+                .server_info
+                .as_ref()
+                .is_some_and(|info| info.relay_code.is_none())
+            && !port_mapped()
     }
 
     fn handle_packet(mut self, pack: S2cPacket) -> (Option<Self>, Option<GameState>) {
         match pack {
+            S2cPacket::SyncServerInfo(info) => {
+                if self.server_info.as_ref().map(|i| &i.name) != Some(&info.name) {
+                    RecentServers::record(&self.target, &info.name);
+                }
+                if self.server_info.as_ref().map(|i| &i.sound_pack) != Some(&info.sound_pack) {
+                    sound_pack::start(&self.tx, info.sound_pack.as_deref());
+                }
+                self.server_info = Some(info);
+                (Some(self), None)
+            }
+            S2cPacket::SyncRoomList(rooms) => {
+                self.rooms = rooms;
+                (Some(self), None)
+            }
+            S2cPacket::SyncRoom(room) => {
+                self.room = Some(room);
+                self.room_error = None;
+                (Some(self), None)
+            }
+            S2cPacket::JoinRoomFailed(msg) => {
+                self.room_error = Some(msg);
+                (Some(self), None)
+            }
             S2cPacket::SyncPlayerList(host, list) => {
                 self.host = host;
                 self.players = list;
@@ -71,6 +271,10 @@ impl LobbyState {
                     Some(GameState::Kicked(KickedState { message: msg })),
                 )
             }
+            S2cPacket::SyncBracket(bracket) => {
+                self.bracket = Some(bracket);
+                (Some(self), None)
+            }
             S2cPacket::SyncGame(game) => {
                 let client_game = ClientGameState {
                     game,
@@ -79,6 +283,10 @@ impl LobbyState {
                     players: self.players,
                     host: self.host,
                     username: self.username,
+                    bracket: self.bracket,
+                    captions: vec![],
+                    pointer_key: None,
+                    pointer_over_ui: false,
                 };
                 (None, Some(GameState::InGame(client_game)))
             }
@@ -87,20 +295,40 @@ impl LobbyState {
                 (Some(self), None)
             }
             S2cPacket::PlaySound(_) => (Some(self), None),
+            S2cPacket::AssetChunk(sha256, offset, bytes) => {
+                sound_pack::receive(&self.tx, sha256, offset, bytes);
+                (Some(self), None)
+            }
         }
     }
 
     pub async fn tick(mut self) -> GameState {
         let mut new_game_state = None;
 
-        if let Ok(Ok(packet)) = self.rx.try_recv() {
-            let (new_self, new_state) = self.handle_packet(packet);
-            if let Some(new_state) = new_state {
-                return new_state;
+        match self.rx.try_recv() {
+            Ok(Ok(packet)) => {
+                let (new_self, new_state) = self.handle_packet(packet);
+                if let Some(new_state) = new_state {
+                    return new_state;
+                }
+                self = new_self.unwrap();
+            }
+            Ok(Err(e)) => {
+                return GameState::Kicked(KickedState {
+                    message: format!("Lost connection to the server ({e})"),
+                });
             }
-            self = new_self.unwrap();
+            Err(_) => {}
         }
         let mut should_start_game = false;
+        let mut join_room = None;
+        let mut create_room = false;
+        let mut should_start_tournament = false;
+        let room_invite = self.room.as_ref().and_then(|room| self.invite(room));
+        let invite_code = room_invite.as_ref().map(Invite::encode);
+        let invite_link = room_invite.as_ref().map(Invite::link);
+        let picks_lan_address = self.picks_lan_address();
+        let port_forwarding = self.port_forwarding();
 
         egui_macroquad::ui(|ctx| {
             egui::CentralPanel::default()
@@ -108,15 +336,125 @@ impl LobbyState {
                 .show(ctx, |ui| {
                     ui.vertical_centered(|ui| {
                         ui.label(RichText::new("Bluescreen Roulette").size(32.0));
+                        if let Some(info) = &self.server_info {
+                            ui.label(&info.name);
+                            if let Some(code) = &info.relay_code {
+                                ui.label(format!("Relay code: {code}"));
+                            }
+                        }
                         let window_pos_x = (screen_width() - 200.0) / 2.0;
                         let window_pos_y = (screen_height() - 200.0) / 2.0;
 
-                        egui::Window::new("Lobby")
+                        let Some(room) = &self.room else {
+                            egui::Window::new("Rooms")
+                                .fixed_pos((window_pos_x, window_pos_y))
+                                .fixed_size((200.0, 200.0))
+                                .collapsible(false)
+                                .resizable(false)
+                                .show(ctx, |ui| {
+                                    egui::Grid::new("rooms").striped(true).show(ui, |ui| {
+                                        for room in &self.rooms {
+                                            if room_browser::room_row(ui, room) {
+                                                join_room = Some(room.code.clone());
+                                            }
+                                        }
+                                    });
+                                    if self.rooms.is_empty() {
+                                        ui.label("No rooms yet");
+                                    }
+                                    if let Some(error) = &self.room_error {
+                                        ui.colored_label(egui::Color32::RED, error);
+                                    }
+                                    ui.separator();
+
+                                    ui.horizontal(|ui| {
+                                        ui.label("Code");
+                                        ui.text_edit_singleline(&mut self.code_edit);
+                                        if ui.button("Join").clicked() {
+                                            join_room = Some(self.code_edit.clone());
+                                        }
+                                    });
+                                    ui.horizontal(|ui| {
+                                        ui.label("Password");
+                                        ui.text_edit_singleline(&mut self.password_edit);
+                                    });
+                                    ui.separator();
+
+                                    ui.horizontal(|ui| {
+                                        ui.label("Name");
+                                        ui.text_edit_singleline(&mut self.room_name_edit);
+                                    });
+                                    ui.horizontal(|ui| {
+                                        ui.label("Max players");
+                                        ui.add(
+                                            egui::DragValue::new(&mut self.max_players_edit)
+                                                .clamp_range(2..=32),
+                                        );
+                                    });
+                                    ui.horizontal(|ui| {
+                                        if ui.button("Leave").clicked() {
+                                            new_game_state =
+                                                Some(GameState::MainMenu(MainMenuState::new()));
+                                        }
+                                        if ui.button("Create room").clicked() {
+                                            create_room = true;
+                                        }
+                                    });
+                                });
+                            return;
+                        };
+
+                        egui::Window::new(&room.name)
+                            .id(egui::Id::new("lobby"))
                             .fixed_pos((window_pos_x, window_pos_y))
                             .fixed_size((200.0, 200.0))
                             .collapsible(false)
                             .resizable(false)
                             .show(ctx, |ui| {
+                                ui.label(format!("Room code: {}", room.code));
+                                if picks_lan_address {
+                                    let selected = self
+                                        .lan_invite_ip
+                                        .map_or("Pick one".to_string(), |ip| ip.to_string());
+                                    egui::ComboBox::from_label("Invite address")
+                                        .selected_text(selected)
+                                        .show_ui(ui, |ui| {
+                                            for (name, ip) in &self.lan_addrs {
+                                                ui.selectable_value(
+                                                    &mut self.lan_invite_ip,
+                                                    Some(*ip),
+                                                    format!("{ip} ({name})"),
+                                                );
+                                            }
+                                        });
+                                }
+                                if let Some(code) = &invite_code {
+                                    ui.horizontal(|ui| {
+                                        ui.label(format!("Invite: {code}"));
+                                        if ui.small_button("Copy").clicked() {
+                                            ui.output_mut(|o| o.copied_text = code.clone());
+                                        }
+                                    });
+                                    if let Some(status) = &port_forwarding {
+                                        ui.label(status);
+                                    }
+                                    if room.locked && !self.password_edit.is_empty() {
+                                        ui.checkbox(
+                                            &mut self.invite_password,
+                                            "Include password in invite",
+                                        );
+                                    }
+                                    if let Some(link) = &invite_link {
+                                        if ui.small_button("Copy link").clicked() {
+                                            ui.output_mut(|o| o.copied_text = link.clone());
+                                        }
+                                        if self.host {
+                                            ui.collapsing("QR code", |ui| {
+                                                invite::qr_code(ui, link)
+                                            });
+                                        }
+                                    }
+                                }
                                 egui::Grid::new("list")
                                     .striped(true)
                                     .min_col_width(200.0)
@@ -136,15 +474,66 @@ impl LobbyState {
                                         should_start_game = true;
                                     }
                                 });
+
+                                if !self.host {
+                                    return;
+                                }
+                                ui.collapsing("Tournament", |ui| {
+                                    ui.horizontal(|ui| {
+                                        ui.label("Table size");
+                                        ui.add(
+                                            egui::DragValue::new(&mut self.table_size)
+                                                .clamp_range(2..=16),
+                                        );
+                                    });
+                                    ui.radio_value(
+                                        &mut self.bracket_mode,
+                                        BracketMode::Survivors,
+                                        "Survivors advance",
+                                    );
+                                    ui.radio_value(
+                                        &mut self.bracket_mode,
+                                        BracketMode::Losers,
+                                        "Loser's bracket",
+                                    );
+                                    if ui.button("Start tournament").clicked() {
+                                        should_start_tournament = true;
+                                    }
+                                });
                             });
                     });
                 });
+            settings_scene::overlay(ctx);
         });
 
         if should_start_game {
             let _ = self.tx.send(C2sPacket::HostStartGame).await;
         }
 
+        let password = Some(self.password_edit.clone()).filter(|p| !p.is_empty());
+        if let Some(code) = join_room {
+            let _ = self.tx.send(C2sPacket::JoinRoom(code, password)).await;
+        } else if create_room {
+            let _ = self
+                .tx
+                .send(C2sPacket::CreateRoom(RoomSettings {
+                    name: self.room_name_edit.clone(),
+                    max_players: self.max_players_edit,
+                    password,
+                }))
+                .await;
+        }
+
+        if should_start_tournament {
+            let _ = self
+                .tx
+                .send(C2sPacket::HostStartTournament(
+                    self.table_size,
+                    self.bracket_mode,
+                ))
+                .await;
+        }
+
         egui_macroquad::draw();
 
         if let Some(new_game_state) = new_game_state {
@@ -154,3 +543,35 @@ impl LobbyState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_links_join_the_room() {
+        let target = JoinTarget::parse("bluescreen-roulette://1.2.3.4:1234/ABCDE").unwrap();
+        assert_eq!(target.addr, "1.2.3.4:1234");
+        assert_eq!(target.room.as_deref(), Some("ABCDE"));
+        assert_eq!(target.relay, None);
+
+        let target = JoinTarget::parse("bluescreen-roulette://example.com/").unwrap();
+        assert_eq!(target.addr, format!("example.com:{PORT}"));
+        assert_eq!(target.room, None);
+        assert!(JoinTarget::parse("bluescreen-roulette://").is_none());
+    }
+
+    #[test]
+    fn invite_links_and_codes_join_the_same_room() {
+        let invite = Invite {
+            route: Route::Direct("192.168.1.20:1234".parse().unwrap()),
+            room: Some("ABCDE".to_string()),
+            password: None,
+        };
+        for value in [invite.link(), invite.encode()] {
+            let target = JoinTarget::parse(&value).unwrap();
+            assert_eq!(target.addr, "192.168.1.20:1234");
+            assert_eq!(target.room.as_deref(), Some("ABCDE"));
+        }
+    }
+}
@@ -1,26 +1,54 @@
 use std::collections::HashMap;
 use std::io::Cursor;
+use std::path::PathBuf;
 
+use audio::Mood;
 use client_game::ClientGameState;
+use connecting::ConnectingState;
 use egui_macroquad::macroquad;
 use egui_macroquad::macroquad::prelude::*;
 use egui_macroquad::macroquad::window::clear_background;
 
+mod asset_check;
+mod assets;
+mod audio;
 mod c2s_packet;
 mod client_game;
+mod connecting;
+mod discovery;
+mod framing;
+mod hot_reload;
+mod invite;
+mod keyboard;
 mod kicked;
 mod lobby;
 mod main_menu;
+mod mods;
+mod network_info;
 mod packet_channel;
+mod port_mapping;
+mod public_addr;
+mod recent_servers;
+mod relay_protocol;
+mod room_browser;
 mod s2c_packet;
 mod server;
+mod settings;
+mod settings_scene;
+mod sound_event;
+mod sound_pack;
+mod tournament;
 use kicked::KickedState;
 use lazy_static::lazy_static;
-use lobby::LobbyState;
+use lobby::{JoinTarget, LobbyState};
 use main_menu::MainMenuState;
+use port_mapping::GatewayConfig;
+use public_addr::Resolver;
+use server::{Server, ServerConfig};
 
 enum GameState {
     MainMenu(MainMenuState),
+    Connecting(ConnectingState),
     Lobby(LobbyState),
     Kicked(KickedState),
     InGame(ClientGameState),
@@ -29,13 +57,18 @@ enum GameState {
 lazy_static! {
     static ref BUNDLE: HashMap<String, Vec<u8>> = {
         let mut m = HashMap::new();
-        let bundle = include_bytes!("bundle.pfa");
+        if hot_reload::enabled() {
+            m = hot_reload::read_bundle();
+        } else {
+            let bundle = include_bytes!("bundle.pfa");
 
-        let mut reader = pfa::reader::PfaReader::new(Cursor::new(bundle)).unwrap();
-        reader.traverse_files("/", |file| {
-            println!("[Bundle] file={}", file.get_path());
-            m.insert(file.get_path().to_string(), file.get_contents().to_vec());
-        });
+            let mut reader = pfa::reader::PfaReader::new(Cursor::new(bundle)).unwrap();
+            reader.traverse_files("/", |file| {
+                println!("[Bundle] file={}", file.get_path());
+                m.insert(file.get_path().to_string(), file.get_contents().to_vec());
+            });
+        }
+        mods::mount(&mut m);
 
         m
     };
@@ -43,14 +76,78 @@ lazy_static! {
 
 #[tokio::main]
 async fn main() {
+    settings::load();
+    let args = std::env::args().collect::<Vec<_>>();
+    let arg_value = |flag: &str| {
+        args.iter()
+            .position(|arg| arg == flag)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    if args.iter().any(|arg| arg == "--dev") {
+        hot_reload::enable();
+    }
+    let resolver = arg_value("--public-ip").unwrap_or(settings::get().public_ip);
+    match Resolver::parse(&resolver) {
+        Some(resolver) => public_addr::set_resolver(resolver),
+        None => eprintln!("Unknown public IP resolver {resolver}, using STUN"),
+    }
+
+    if args.iter().any(|arg| arg == "--dedicated") {
+        Server::start(ServerConfig {
+            dedicated: true,
+            name: arg_value("--server-name").unwrap_or("Dedicated server".to_string()),
+            relay: arg_value("--relay"),
+            port_mapping: (args.iter().any(|arg| arg == "--map-port")
+                || arg_value("--gateway").is_some())
+            .then(|| GatewayConfig {
+                gateway: arg_value("--gateway").and_then(|ip| ip.parse().ok()),
+                ..Default::default()
+            }),
+            sound_pack: arg_value("--sound-pack").map(PathBuf::from),
+        })
+        .await;
+        return;
+    }
+
+    // Links opened through the URL scheme arrive as a bare argument
+    let join = arg_value("--join")
+        .or_else(|| {
+            args.iter()
+                .skip(1)
+                .find(|arg| arg.starts_with(invite::URL_SCHEME))
+                .cloned()
+        })
+        .map(|join| (JoinTarget::parse(&join), join));
+    let name = arg_value("--name")
+        .or(Some(settings::get().username).filter(|name| !name.is_empty()))
+        .unwrap_or("Player".to_string());
+
     macroquad::Window::new("Bluescreen Roulette", async move {
-        let mut game_state = GameState::MainMenu(MainMenuState::new());
+        set_fullscreen(settings::get().fullscreen);
+        audio::preload().await;
+        let mut game_state = match join {
+            Some((Some(target), _)) => LobbyState::try_new(&name, target),
+            Some((None, join)) => {
+                eprintln!("Can't join {join}");
+                GameState::MainMenu(MainMenuState::new())
+            }
+            None => GameState::MainMenu(MainMenuState::new()),
+        };
         loop {
             clear_background(BLACK);
+            audio::set_mood(match &game_state {
+                GameState::InGame(game) => game.mood(),
+                _ => Mood::Lobby,
+            });
+            audio::update().await;
             match game_state {
                 GameState::MainMenu(main_menu) => {
                     game_state = main_menu.tick();
                 }
+                GameState::Connecting(connecting) => {
+                    game_state = connecting.tick();
+                }
                 GameState::Lobby(lobby) => {
                     game_state = lobby.tick().await;
                 }
@@ -1,77 +1,82 @@
-use std::net::IpAddr;
-use std::ops::Deref;
-use std::sync::mpsc::{Receiver, Sender};
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
 
 use egui_macroquad::egui::{self, RichText};
 use egui_macroquad::macroquad::prelude::*;
 
-use crate::lobby::LobbyState;
-use crate::server::Server;
+use crate::discovery::LanDiscovery;
+use crate::lobby::{JoinTarget, LobbyState};
+use crate::network_info::NetworkInfo;
+use crate::port_mapping::{self, GatewayConfig, MappingStatus};
+use crate::public_addr::{self, Lookup};
+use crate::recent_servers::RecentServers;
+use crate::relay_protocol::RELAY_PORT;
+use crate::room_browser::RoomBrowser;
+use crate::server::{Server, ServerConfig, PORT};
 use crate::GameState;
-
-enum PubIpResolveStage {
-    Waiting(Receiver<Option<String>>),
-    Done(String),
-}
+use crate::{settings, settings_scene, sound_pack};
 
 pub struct MainMenuState {
-    ip: Option<String>,
-    pub_ip: Arc<Mutex<PubIpResolveStage>>,
+    network: NetworkInfo,
     ip_edit: String,
     username_edit: String,
+    browser: Option<RoomBrowser>,
+    recent: RecentServers,
+    lan: LanDiscovery,
+    relay_edit: String,
+    relay_code_edit: String,
+    host_via_relay: bool,
+    gateway_edit: String,
+    sound_pack_edit: String,
+    /// Why the IP field could not be used, shown under it.
+    ip_error: Option<String>,
 }
 
 impl MainMenuState {
     pub fn new() -> Self {
-        let (tx, rx) = std::sync::mpsc::channel();
-        std::thread::spawn(move || {
-            Self::resolve_pub_ip(tx);
-        });
-
+        sound_pack::reset();
+        let settings = settings::get();
         Self {
-            ip: None,
-            pub_ip: Arc::new(Mutex::new(PubIpResolveStage::Waiting(rx))),
-            ip_edit: String::new(),
-            username_edit: String::new(),
+            network: NetworkInfo::new(),
+            ip_edit: settings.last_ip,
+            username_edit: settings.username,
+            browser: None,
+            recent: RecentServers::load(),
+            lan: LanDiscovery::start(),
+            relay_edit: String::new(),
+            relay_code_edit: String::new(),
+            host_via_relay: false,
+            gateway_edit: String::new(),
+            sound_pack_edit: settings.sound_pack,
+            ip_error: None,
         }
     }
 
-    fn resolve_pub_ip(tx: Sender<Option<String>>) {
-        let req = ureq::get("http://api.ipify.org");
-        let Ok(resp) = req.call() else {
-            let _ = tx.send(None);
-            return;
-        };
-        let Ok(resp) = resp.into_string() else {
-            let _ = tx.send(None);
-            return;
-        };
-        let _ = tx.send(Some(resp));
+    fn relay_addr(&self) -> Option<String> {
+        let relay = self.relay_edit.trim();
+        if relay.is_empty() {
+            None
+        } else if relay.contains(':') {
+            Some(relay.to_string())
+        } else {
+            Some(format!("{relay}:{RELAY_PORT}"))
+        }
     }
 
     pub fn tick(mut self) -> GameState {
-        let ip = self
-            .ip
-            .clone()
-            .unwrap_or_else(|| Self::get_local_ip().unwrap_or("Unknown".to_string()));
-
-        let pub_ip = match self.pub_ip.lock().unwrap().deref() {
-            PubIpResolveStage::Waiting(rx) => {
-                if let Ok(val) = rx.try_recv() {
-                    val
-                } else {
-                    None
-                }
-            }
-            PubIpResolveStage::Done(text) => Some(text.to_owned()),
+        let pub_ip = match public_addr::lookup() {
+            Lookup::NotStarted | Lookup::Resolving => "...".to_string(),
+            Lookup::Done(addr) => addr.to_string(),
+            Lookup::Failed(e) => format!("unknown ({e})"),
+            Lookup::Disabled => "not looked up".to_string(),
         };
-
-        if let Some(pub_ip) = pub_ip.to_owned() {
-            *self.pub_ip.lock().unwrap() = PubIpResolveStage::Done(pub_ip);
-        }
-
-        let pub_ip = pub_ip.unwrap_or("...".to_string());
+        let mapping = match port_mapping::STATUS.lock().unwrap().clone() {
+            MappingStatus::Off => String::new(),
+            MappingStatus::Pending => format!(", forwarding port {PORT}..."),
+            MappingStatus::Mapped { description, .. } => format!(", {description}"),
+            MappingStatus::Failed(e) => format!(", port forwarding failed: {e}"),
+        };
+        // Read every frame, the settings window can change it too
+        let mut map_port = settings::get().map_port;
         let mut new_gamestate = None;
 
         egui_macroquad::ui(|ctx| {
@@ -81,12 +86,8 @@ impl MainMenuState {
                     ui.vertical_centered(|ui| {
                         ui.label(RichText::new("Bluescreen Roulette").size(32.0));
                         ui.label(format!(
-                            "Your local IP is: {} (if you're playing via LAN)",
-                            ip
-                        ));
-                        ui.label(format!(
-                            "Your public IP is: {} (if you're playing via Internet)",
-                            pub_ip
+                            "Your public address is: {} (if you're playing via Internet{})",
+                            pub_ip, mapping
                         ));
                     });
 
@@ -107,57 +108,204 @@ impl MainMenuState {
 
                             ui.horizontal(|ui| {
                                 ui.label("IP");
-                                ui.text_edit_singleline(&mut self.ip_edit);
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut self.ip_edit)
+                                        .hint_text("IP or invite code"),
+                                );
+                            });
+                            ui.horizontal(|ui| {
+                                let connect = ui.button("Connect to server").clicked();
+                                let browse = ui.button("Browse rooms").clicked();
+                                if connect || browse {
+                                    match JoinTarget::parse(&self.ip_edit) {
+                                        Some(target) if connect => {
+                                            new_gamestate = Some(LobbyState::try_new(
+                                                &self.username_edit,
+                                                target,
+                                            ));
+                                        }
+                                        Some(target) => {
+                                            self.ip_error = None;
+                                            self.browser = Some(RoomBrowser::query(target));
+                                        }
+                                        None => {
+                                            self.ip_error = Some(
+                                                "Enter an IP address, invite code or link"
+                                                    .to_string(),
+                                            );
+                                        }
+                                    }
+                                }
                             });
-                            if ui.button("Connect to server").clicked() {
-                                new_gamestate = Some(LobbyState::try_new(
-                                    &self.username_edit,
-                                    &format!("{}:1234", self.ip_edit),
-                                ));
+                            if let Some(error) = &self.ip_error {
+                                ui.colored_label(egui::Color32::RED, error);
                             }
+                            egui::CollapsingHeader::new("Servers")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    if let Some(target) = self.recent.ui(ui) {
+                                        new_gamestate =
+                                            Some(LobbyState::try_new(&self.username_edit, target));
+                                    }
+                                });
+                            ui.collapsing("Relay", |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Relay");
+                                    ui.text_edit_singleline(&mut self.relay_edit);
+                                });
+                                ui.horizontal(|ui| {
+                                    ui.label("Code");
+                                    ui.text_edit_singleline(&mut self.relay_code_edit);
+                                });
+                                let relay = self.relay_addr();
+                                if ui
+                                    .add_enabled(
+                                        relay.is_some(),
+                                        egui::Button::new("Join via relay"),
+                                    )
+                                    .clicked()
+                                {
+                                    let mut target = JoinTarget::server(&relay.unwrap_or_default());
+                                    target.relay = Some(self.relay_code_edit.clone());
+                                    new_gamestate =
+                                        Some(LobbyState::try_new(&self.username_edit, target));
+                                }
+                                ui.checkbox(&mut self.host_via_relay, "Host through relay");
+                            });
+                            ui.collapsing("Port forwarding", |ui| {
+                                if ui
+                                    .checkbox(&mut map_port, "Forward port when hosting")
+                                    .changed()
+                                {
+                                    settings::update(|settings| settings.map_port = map_port);
+                                }
+                                ui.horizontal(|ui| {
+                                    ui.label("Gateway");
+                                    ui.add_enabled(
+                                        map_port,
+                                        egui::TextEdit::singleline(&mut self.gateway_edit)
+                                            .hint_text("automatic"),
+                                    );
+                                });
+                            });
+                            ui.collapsing("Sound pack", |ui| {
+                                ui.horizontal(|ui| {
+                                    ui.label("Folder");
+                                    let response = ui.add(
+                                        egui::TextEdit::singleline(&mut self.sound_pack_edit)
+                                            .hint_text("built-in sounds"),
+                                    );
+                                    if response.lost_focus() {
+                                        let sound_pack = self.sound_pack_edit.clone();
+                                        settings::update(|settings| {
+                                            settings.sound_pack = sound_pack
+                                        });
+                                    }
+                                });
+                                ui.label("Players download these sounds when they join.");
+                            });
                             ui.separator();
                             ui.horizontal(|ui| {
                                 ui.label("or");
                                 if ui.button("Host server").clicked() {
+                                    let config = ServerConfig {
+                                        dedicated: false,
+                                        name: format!("{}'s server", self.username_edit.trim()),
+                                        relay: self.relay_addr().filter(|_| self.host_via_relay),
+                                        port_mapping: map_port.then(|| GatewayConfig {
+                                            gateway: self.gateway_edit.trim().parse().ok(),
+                                            ..Default::default()
+                                        }),
+                                        sound_pack: Some(self.sound_pack_edit.trim())
+                                            .filter(|dir| !dir.is_empty())
+                                            .map(PathBuf::from),
+                                    };
                                     tokio::spawn(async move {
-                                        Server::start().await;
+                                        Server::start(config).await;
                                     });
                                     new_gamestate = Some(LobbyState::try_new(
                                         &self.username_edit,
-                                        "127.0.0.1:1234",
+                                        JoinTarget::server(&format!("127.0.0.1:{PORT}")),
                                     ));
                                 }
                             });
                         });
+
+                    egui::Window::new("Network")
+                        .default_pos((window_pos_x - 300.0, window_pos_y + 200.0))
+                        .show(ctx, |ui| self.network.ui(ui));
+
+                    egui::Window::new("LAN servers")
+                        .default_pos((window_pos_x - 300.0, window_pos_y))
+                        .collapsible(false)
+                        .show(ctx, |ui| {
+                            if let Some(error) = &self.lan.error {
+                                ui.colored_label(egui::Color32::RED, error);
+                            } else if self.lan.servers().is_empty() {
+                                ui.label("Looking for servers on your network...");
+                            }
+                            let servers = self.lan.servers();
+                            egui::Grid::new("lan").striped(true).show(ui, |ui| {
+                                for server in servers {
+                                    let announcement = &server.announcement;
+                                    ui.label(&announcement.name);
+                                    ui.label(format!("{} players", announcement.players));
+                                    ui.label(if announcement.in_game {
+                                        "In game"
+                                    } else {
+                                        "Waiting"
+                                    });
+                                    if ui.button("Join").clicked() {
+                                        new_gamestate = Some(LobbyState::try_new(
+                                            &self.username_edit,
+                                            JoinTarget::server(&server.addr.to_string()),
+                                        ));
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        });
+
+                    let mut close_browser = false;
+                    if let Some(browser) = self.browser.as_mut() {
+                        egui::Window::new("Rooms")
+                            .default_pos((window_pos_x + 250.0, window_pos_y))
+                            .collapsible(false)
+                            .show(ctx, |ui| {
+                                if let Some(code) = browser.ui(ui) {
+                                    let password = Some(browser.password_edit.clone())
+                                        .filter(|p| !p.is_empty());
+                                    new_gamestate = Some(LobbyState::try_new(
+                                        &self.username_edit,
+                                        JoinTarget {
+                                            room: Some(code),
+                                            password,
+                                            ..browser.target.clone()
+                                        },
+                                    ));
+                                }
+                                close_browser = ui.button("Close").clicked();
+                            });
+                    }
+                    if close_browser {
+                        self.browser = None;
+                    }
                 });
+            settings_scene::overlay(ctx);
         });
         egui_macroquad::draw();
 
         if let Some(new_gamestate) = new_gamestate {
+            let settings = settings::get();
+            if settings.username != self.username_edit || settings.last_ip != self.ip_edit {
+                settings::update(|settings| {
+                    settings.username = self.username_edit.clone();
+                    settings.last_ip = self.ip_edit.clone();
+                });
+            }
             new_gamestate
         } else {
             GameState::MainMenu(self)
         }
     }
-    fn get_local_ip() -> Option<String> {
-        if let Ok(ifaces) = get_if_addrs::get_if_addrs() {
-            for iface in ifaces {
-                if iface.name.starts_with("vir") {
-                    continue;
-                }
-
-                // Check if the interface is up and not a loopback interface
-                if !iface.is_loopback() {
-                    match iface.ip() {
-                        IpAddr::V4(v4) => {
-                            return Some(v4.to_string());
-                        }
-
-                        IpAddr::V6(_) => {}
-                    }
-                }
-            }
-        }
-        None
-    }
 }
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::{asset_check, settings};
+
+/// Describes a mod. Optional, mods without one are named after their file.
+const MANIFEST: &str = "/mod.toml";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ModManifest {
+    pub name: String,
+    pub version: String,
+    pub author: String,
+    pub description: String,
+    /// Mods with a higher priority are mounted later, so their files win.
+    pub priority: i32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Mod {
+    pub manifest: ModManifest,
+    pub source: PathBuf,
+    pub files: usize,
+    /// Files that replaced one from the built-in bundle or an earlier mod.
+    pub overrides: usize,
+}
+
+lazy_static! {
+    static ref LOADED: Mutex<Vec<Mod>> = Mutex::new(vec![]);
+}
+
+/// Where mods are looked for, `bluescreen-roulette/mods` in the config directory.
+pub fn folder() -> Option<PathBuf> {
+    settings::config_path("mods")
+}
+
+pub fn loaded() -> Vec<Mod> {
+    LOADED.lock().unwrap().clone()
+}
+
+/// Mounts every `.pfa` archive and folder in the mods folder over the bundle,
+/// in order of priority and then name.
+pub fn mount(bundle: &mut HashMap<String, Vec<u8>>) {
+    let Some(entries) = folder().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return;
+    };
+    let mut mods = vec![];
+    for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+        let files = if path.is_dir() {
+            read_dir(&path)
+        } else if path.extension().is_some_and(|ext| ext == "pfa") {
+            read_pfa(&path)
+        } else {
+            continue;
+        };
+        match files.and_then(|files| manifest(&path, &files).map(|m| (m, files))) {
+            Ok((manifest, files)) => mods.push((manifest, path, files)),
+            Err(e) => eprintln!("[Mods] skipping {}: {e}", path.display()),
+        }
+    }
+    mods.sort_by(|a, b| (a.0.priority, &a.0.name).cmp(&(b.0.priority, &b.0.name)));
+
+    let mut loaded = LOADED.lock().unwrap();
+    for (manifest, source, mut files) in mods {
+        files.remove(MANIFEST);
+        let mut count = 0;
+        let mut overrides = 0;
+        for (path, contents) in files {
+            // A broken file would only show up as a silent sound later
+            if let Err(e) = asset_check::check(&path, &contents) {
+                eprintln!("[Mods] skipping {path} from {}: {e}", manifest.name);
+                continue;
+            }
+            count += 1;
+            if bundle.insert(path, contents).is_some() {
+                overrides += 1;
+            }
+        }
+        println!(
+            "[Mods] mounted {} from {} ({count} files, {overrides} overridden)",
+            manifest.name,
+            source.display()
+        );
+        loaded.push(Mod {
+            manifest,
+            source,
+            files: count,
+            overrides,
+        });
+    }
+}
+
+fn manifest(path: &Path, files: &HashMap<String, Vec<u8>>) -> Result<ModManifest> {
+    let mut manifest = match files.get(MANIFEST) {
+        Some(bytes) => toml::from_str(std::str::from_utf8(bytes)?)?,
+        None => ModManifest::default(),
+    };
+    if manifest.name.is_empty() {
+        manifest.name = path.file_stem().map_or("Unnamed mod".to_string(), |s| {
+            s.to_string_lossy().to_string()
+        });
+    }
+    Ok(manifest)
+}
+
+/// Files under a folder, keyed by bundle path like `/shoot.ogg`.
+fn read_dir(root: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let mut files = HashMap::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(root)?;
+            let key = relative
+                .components()
+                .map(|c| format!("/{}", c.as_os_str().to_string_lossy()))
+                .collect::<String>();
+            files.insert(key, std::fs::read(&path)?);
+        }
+    }
+    Ok(files)
+}
+
+fn read_pfa(path: &Path) -> Result<HashMap<String, Vec<u8>>> {
+    let bytes = std::fs::read(path)?;
+    let mut reader =
+        pfa::reader::PfaReader::new(Cursor::new(bytes)).map_err(|e| anyhow!("{e:?}"))?;
+    let mut files = HashMap::new();
+    reader.traverse_files("/", |file| {
+        files.insert(file.get_path().to_string(), file.get_contents().to_vec());
+    });
+    Ok(files)
+}
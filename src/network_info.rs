@@ -0,0 +1,97 @@
+use std::net::IpAddr;
+
+use egui_macroquad::egui;
+
+use crate::server::{self, BIND_ADDR, PORT};
+
+struct NetworkInterface {
+    name: String,
+    loopback: bool,
+    addrs: Vec<IpAddr>,
+}
+
+/// Every network interface of this machine, grouped by name.
+pub struct NetworkInfo {
+    interfaces: Vec<NetworkInterface>,
+}
+
+/// Addresses of the server hosted here that other machines can reach, with the name of their
+/// interface. Loopback is left out since only this computer can use it.
+pub fn server_addrs() -> Vec<(String, IpAddr)> {
+    let Ok(ifaces) = get_if_addrs::get_if_addrs() else {
+        return vec![];
+    };
+    ifaces
+        .into_iter()
+        .filter(|iface| !iface.is_loopback() && server::listening() && binds(&iface.ip()))
+        .map(|iface| (iface.name.clone(), iface.ip()))
+        .collect()
+}
+
+/// Whether a server hosted here takes connections on `ip`, going by `BIND_ADDR`.
+fn binds(ip: &IpAddr) -> bool {
+    BIND_ADDR == *ip || (BIND_ADDR.is_unspecified() && BIND_ADDR.is_ipv4() == ip.is_ipv4())
+}
+
+impl NetworkInfo {
+    pub fn new() -> Self {
+        let mut info = Self { interfaces: vec![] };
+        info.refresh();
+        info
+    }
+
+    pub fn refresh(&mut self) {
+        self.interfaces.clear();
+        let Ok(ifaces) = get_if_addrs::get_if_addrs() else {
+            return;
+        };
+        for iface in ifaces {
+            let loopback = iface.is_loopback();
+            let ip = iface.ip();
+            match self.interfaces.iter_mut().find(|i| i.name == iface.name) {
+                Some(existing) => existing.addrs.push(ip),
+                None => self.interfaces.push(NetworkInterface {
+                    name: iface.name,
+                    loopback,
+                    addrs: vec![ip],
+                }),
+            }
+        }
+        for iface in &mut self.interfaces {
+            iface.addrs.sort_by_key(IpAddr::is_ipv6);
+        }
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        if self.interfaces.is_empty() {
+            ui.label("No network interfaces found");
+        }
+        egui::Grid::new("network").striped(true).show(ui, |ui| {
+            for iface in &self.interfaces {
+                for (i, ip) in iface.addrs.iter().enumerate() {
+                    if i == 0 && iface.loopback {
+                        ui.label(format!("{} (this computer only)", iface.name));
+                    } else if i == 0 {
+                        ui.label(&iface.name);
+                    } else {
+                        ui.label("");
+                    }
+                    ui.label(ip.to_string());
+                    ui.label(if binds(ip) {
+                        format!("Server port {PORT}")
+                    } else {
+                        String::new()
+                    });
+                    if ui.small_button("Copy").clicked() {
+                        ui.output_mut(|o| o.copied_text = ip.to_string());
+                    }
+                    ui.end_row();
+                }
+            }
+        });
+        ui.label("Share an address from the interface on the same network as your friends.");
+        if ui.button("Refresh").clicked() {
+            self.refresh();
+        }
+    }
+}
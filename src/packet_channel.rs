@@ -1,9 +1,29 @@
+use std::fmt;
+
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-};
+use tokio::{io::AsyncRead, net::TcpStream};
+
+use crate::framing;
+
+/// A frame arrived but didn't hold a packet we know, usually because the other side runs another version.
+#[derive(Debug)]
+pub struct DecodeError;
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to decode packet")
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+async fn read_packet<R: for<'a> Deserialize<'a>>(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> Result<R> {
+    let data = framing::read_frame(stream).await?;
+    bincode::deserialize::<R>(&data).map_err(|_| DecodeError.into())
+}
 
 pub fn async_channel<S: Serialize + Send + 'static, R: for<'a> Deserialize<'a> + Send + 'static>(
     stream: TcpStream,
@@ -13,43 +33,27 @@ pub fn async_channel<S: Serialize + Send + 'static, R: for<'a> Deserialize<'a> +
 ) {
     let (in_tx, in_rx) = tokio::sync::mpsc::channel(1024);
     let (out_tx, mut out_rx) = tokio::sync::mpsc::channel(1024);
+    let (mut read, mut write) = stream.into_split();
 
+    let closed_tx = in_tx.clone();
     tokio::spawn(async move {
-        let mut stream = stream;
-
         loop {
-            let mut data = vec![0; 1024];
-            tokio::select! {
-                len = stream.read(&mut data) => {
-                    if let Ok(len) = len {
-                        if len == 0 {
-                            in_tx.send(Err(anyhow!("EOF"))).await.unwrap();
-                            break;
-                        }
-
-                        let data = &data[..len];
-                        let Ok(data) = bincode::deserialize::<R>(data) else {
-                            in_tx.send(Err(anyhow!("failed to decode packet"))).await.unwrap();
-                            break;
-                        };
-                        in_tx.send(Ok(data)).await.unwrap();
-                    } else {
-                        in_tx.send(Err(anyhow!("Error"))).await.unwrap();
-                        break;
-                    }
-
-                }
-                data = out_rx.recv() => {
-                    if let Some(data) = data {
-                        let data = bincode::serialize(&data).unwrap();
-                        stream.write_all(&data).await.unwrap();
-                    } else {
-                        let _ = in_tx.send(Err(anyhow!("Error"))).await;
-                        break;
-                    }
-                }
-            };
+            let packet = read_packet::<R>(&mut read).await;
+            let failed = packet.is_err();
+            if in_tx.send(packet).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(data) = out_rx.recv().await {
+            let data = bincode::serialize(&data).unwrap();
+            if framing::write_frame(&mut write, &data).await.is_err() {
+                break;
+            }
         }
+        let _ = closed_tx.send(Err(anyhow!("Error"))).await;
     });
     (in_rx, out_tx)
 }
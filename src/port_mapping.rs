@@ -0,0 +1,441 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+
+const SSDP_MULTICAST: IpAddr = IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250));
+/// Seconds a mapping lasts unless renewed, so a crashed server doesn't leave it open.
+const LEASE: u32 = 60 * 60;
+/// How often the server should call `renew`, well within `LEASE`.
+pub const RENEW_INTERVAL: Duration = Duration::from_secs(LEASE as u64 / 3);
+const TIMEOUT: Duration = Duration::from_secs(2);
+const DESCRIPTION: &str = "Bluescreen Roulette";
+const WAN_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:1",
+    "urn:schemas-upnp-org:service:WANPPPConnection:1",
+];
+
+#[derive(Debug, Clone)]
+pub enum MappingStatus {
+    Off,
+    Pending,
+    /// The port the gateway forwards from the outside, and how.
+    Mapped {
+        external_port: u16,
+        description: String,
+    },
+    Failed(String),
+}
+
+lazy_static! {
+    /// State of the mapping made by the server hosted from this client, for the menus to show.
+    pub static ref STATUS: Mutex<MappingStatus> = Mutex::new(MappingStatus::Off);
+}
+
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    /// Gateway to talk to directly instead of searching for one.
+    pub gateway: Option<IpAddr>,
+    pub ssdp_port: u16,
+    pub natpmp_port: u16,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            gateway: None,
+            ssdp_port: 1900,
+            natpmp_port: 5351,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Mapper {
+    Upnp {
+        control_url: String,
+        service: String,
+        local_ip: IpAddr,
+    },
+    NatPmp {
+        gateway: SocketAddr,
+    },
+}
+
+#[derive(Debug)]
+pub struct PortMapping {
+    mapper: Mapper,
+    port: u16,
+}
+
+impl PortMapping {
+    /// Asks the gateway to forward TCP `port` to this machine, trying UPnP first and NAT-PMP second.
+    pub fn create(config: &GatewayConfig, port: u16) -> Result<Self> {
+        let upnp_err = match Self::create_upnp(config, port) {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => e,
+        };
+        let natpmp_err = match Self::create_natpmp(config, port) {
+            Ok(mapping) => return Ok(mapping),
+            Err(e) => e,
+        };
+        Err(anyhow!("UPnP: {upnp_err}, NAT-PMP: {natpmp_err}"))
+    }
+
+    /// The port other players connect to from the Internet, which the gateway forwards to ours.
+    pub fn external_port(&self) -> u16 {
+        // Both mappers insist on the same port outside as inside
+        self.port
+    }
+
+    pub fn describe(&self) -> String {
+        match self.mapper {
+            Mapper::Upnp { .. } => format!("port {} forwarded via UPnP", self.port),
+            Mapper::NatPmp { .. } => format!("port {} forwarded via NAT-PMP", self.port),
+        }
+    }
+
+    /// Extends the lease, which runs out after `LEASE` seconds otherwise.
+    pub fn renew(&self) -> Result<()> {
+        match &self.mapper {
+            Mapper::Upnp {
+                control_url,
+                service,
+                local_ip,
+            } => add_upnp(control_url, service, *local_ip, self.port),
+            Mapper::NatPmp { gateway } => {
+                natpmp_request(*gateway, self.port, self.port, LEASE).map(|_| ())
+            }
+        }
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        match &self.mapper {
+            Mapper::Upnp {
+                control_url,
+                service,
+                ..
+            } => {
+                let args = format!(
+                    "<NewRemoteHost></NewRemoteHost>\
+                     <NewExternalPort>{}</NewExternalPort>\
+                     <NewProtocol>TCP</NewProtocol>",
+                    self.port
+                );
+                soap_call(control_url, service, "DeletePortMapping", &args)
+            }
+            Mapper::NatPmp { gateway } => natpmp_request(*gateway, self.port, 0, 0).map(|_| ()),
+        }
+    }
+
+    fn create_upnp(config: &GatewayConfig, port: u16) -> Result<Self> {
+        let ssdp_addr = SocketAddr::new(config.gateway.unwrap_or(SSDP_MULTICAST), config.ssdp_port);
+        let location = ssdp_search(ssdp_addr)?;
+        let description = ureq::get(&location)
+            .timeout(TIMEOUT)
+            .call()?
+            .into_string()?;
+        let (service, control_url) = WAN_SERVICES
+            .iter()
+            .find_map(|service| {
+                let after = &description[description.find(service)?..];
+                Some((service.to_string(), xml_value(after, "controlURL")?))
+            })
+            .ok_or(anyhow!("gateway does not offer a WAN connection service"))?;
+        let control_url = resolve_url(&location, &control_url);
+
+        let gateway = location
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split('/').next())
+            .ok_or(anyhow!("bad gateway location {location}"))?;
+        let local_ip = local_ip_towards(gateway)?;
+        add_upnp(&control_url, &service, local_ip, port)?;
+
+        Ok(Self {
+            mapper: Mapper::Upnp {
+                control_url,
+                service,
+                local_ip,
+            },
+            port,
+        })
+    }
+
+    fn create_natpmp(config: &GatewayConfig, port: u16) -> Result<Self> {
+        let gateway = match config.gateway {
+            Some(ip) => ip,
+            None => guess_gateway()?,
+        };
+        let gateway = SocketAddr::new(gateway, config.natpmp_port);
+        let external = natpmp_request(gateway, port, port, LEASE)?;
+        if external != port {
+            let _ = natpmp_request(gateway, port, 0, 0);
+            return Err(anyhow!("gateway offered port {external} instead of {port}"));
+        }
+
+        Ok(Self {
+            mapper: Mapper::NatPmp { gateway },
+            port,
+        })
+    }
+}
+
+fn add_upnp(control_url: &str, service: &str, local_ip: IpAddr, port: u16) -> Result<()> {
+    let args = format!(
+        "<NewRemoteHost></NewRemoteHost>\
+         <NewExternalPort>{port}</NewExternalPort>\
+         <NewProtocol>TCP</NewProtocol>\
+         <NewInternalPort>{port}</NewInternalPort>\
+         <NewInternalClient>{local_ip}</NewInternalClient>\
+         <NewEnabled>1</NewEnabled>\
+         <NewPortMappingDescription>{DESCRIPTION}</NewPortMappingDescription>\
+         <NewLeaseDuration>{LEASE}</NewLeaseDuration>"
+    );
+    soap_call(control_url, service, "AddPortMapping", &args)
+}
+
+fn ssdp_search(addr: SocketAddr) -> Result<String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    let search = format!(
+        "M-SEARCH * HTTP/1.1\r\n\
+         HOST: {addr}\r\n\
+         MAN: \"ssdp:discover\"\r\n\
+         MX: 2\r\n\
+         ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\r\n"
+    );
+    socket.send_to(search.as_bytes(), addr)?;
+
+    let mut data = vec![0; 2048];
+    let (len, _) = socket
+        .recv_from(&mut data)
+        .map_err(|_| anyhow!("no gateway answered"))?;
+    String::from_utf8_lossy(&data[..len])
+        .lines()
+        .find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case("location")
+                .then(|| value.trim().to_string())
+        })
+        .ok_or(anyhow!("gateway answer has no location"))
+}
+
+fn soap_call(control_url: &str, service: &str, action: &str, args: &str) -> Result<()> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\
+         <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+         <s:Body><u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body>\
+         </s:Envelope>"
+    );
+    ureq::post(control_url)
+        .timeout(TIMEOUT)
+        .set("Content-Type", "text/xml; charset=\"utf-8\"")
+        .set("SOAPAction", &format!("\"{service}#{action}\""))
+        .send_string(&body)
+        .map_err(|e| anyhow!("{action} failed: {e}"))?;
+    Ok(())
+}
+
+/// Sends a NAT-PMP TCP mapping request and returns the external port granted.
+fn natpmp_request(gateway: SocketAddr, port: u16, external: u16, lifetime: u32) -> Result<u16> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+
+    let mut request = vec![0, 2, 0, 0];
+    request.extend(port.to_be_bytes());
+    request.extend(external.to_be_bytes());
+    request.extend(lifetime.to_be_bytes());
+    socket.send_to(&request, gateway)?;
+
+    let mut response = [0; 16];
+    let (len, _) = socket
+        .recv_from(&mut response)
+        .map_err(|_| anyhow!("no gateway answered"))?;
+    if len < 16 || response[1] != 130 {
+        return Err(anyhow!("malformed answer from gateway"));
+    }
+    let result = u16::from_be_bytes([response[2], response[3]]);
+    if result != 0 {
+        return Err(anyhow!("gateway refused with code {result}"));
+    }
+    Ok(u16::from_be_bytes([response[10], response[11]]))
+}
+
+fn local_ip_towards(addr: &str) -> Result<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(addr)?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// Home routers almost always sit at the first address of the local subnet.
+fn guess_gateway() -> Result<IpAddr> {
+    let IpAddr::V4(ip) = local_ip_towards("1.1.1.1:80")? else {
+        return Err(anyhow!("no IPv4 address"));
+    };
+    let [a, b, c, _] = ip.octets();
+    Ok(IpAddr::V4(Ipv4Addr::new(a, b, c, 1)))
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{tag}>"))?;
+    Some(xml[start..end].trim().to_string())
+}
+
+fn resolve_url(location: &str, url: &str) -> String {
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return url.to_string();
+    }
+    let base_end = location
+        .find("://")
+        .and_then(|scheme| location[scheme + 3..].find('/').map(|i| scheme + 3 + i))
+        .unwrap_or(location.len());
+    format!("{}/{}", &location[..base_end], url.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        sync::{mpsc, Arc},
+    };
+
+    use super::*;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /// Answers SSDP searches and serves a gateway description and control
+    /// URL, sending every SOAP action it receives down the channel.
+    fn fake_igd() -> (u16, mpsc::Receiver<String>) {
+        let http = TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let http_port = http.local_addr().unwrap().port();
+        let ssdp = UdpSocket::bind((LOCALHOST, 0)).unwrap();
+        let ssdp_port = ssdp.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let mut data = [0; 2048];
+            while let Ok((len, from)) = ssdp.recv_from(&mut data) {
+                let search = String::from_utf8_lossy(&data[..len]);
+                assert!(search.contains(&format!("HOST: 127.0.0.1:{ssdp_port}")));
+                let answer = format!(
+                    "HTTP/1.1 200 OK\r\nLOCATION: http://127.0.0.1:{http_port}/desc.xml\r\n\r\n"
+                );
+                ssdp.send_to(answer.as_bytes(), from).unwrap();
+            }
+        });
+
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in http.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((key, value)) = line.split_once(':') {
+                        if key.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let response = if request_line.starts_with("GET /desc.xml") {
+                    "<root><device><serviceList><service>\
+                     <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+                     <controlURL>/ctl/IPConn</controlURL>\
+                     </service></serviceList></device></root>"
+                        .to_string()
+                } else {
+                    tx.send(String::from_utf8(body).unwrap()).unwrap();
+                    String::new()
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                    response.len()
+                )
+                .unwrap();
+            }
+        });
+        (ssdp_port, rx)
+    }
+
+    /// Grants every NAT-PMP request as asked, recording the requested lifetimes.
+    fn fake_natpmp() -> (u16, Arc<Mutex<Vec<u32>>>) {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let lifetimes = Arc::new(Mutex::new(vec![]));
+        let recorded = lifetimes.clone();
+        std::thread::spawn(move || {
+            let mut data = [0; 12];
+            while let Ok((_, from)) = socket.recv_from(&mut data) {
+                let internal = [data[4], data[5]];
+                let external = [data[6], data[7]];
+                let lifetime = [data[8], data[9], data[10], data[11]];
+                recorded.lock().unwrap().push(u32::from_be_bytes(lifetime));
+                let mut answer = vec![0, 128 + data[1], 0, 0, 0, 0, 0, 1];
+                answer.extend(internal);
+                answer.extend(external);
+                answer.extend(lifetime);
+                socket.send_to(&answer, from).unwrap();
+            }
+        });
+        (port, lifetimes)
+    }
+
+    #[test]
+    fn upnp_mapping_is_leased_renewed_and_removed() {
+        let (ssdp_port, actions) = fake_igd();
+        let config = GatewayConfig {
+            gateway: Some(LOCALHOST),
+            ssdp_port,
+            ..Default::default()
+        };
+        let mapping = PortMapping::create_upnp(&config, 1234).unwrap();
+        assert_eq!(mapping.describe(), "port 1234 forwarded via UPnP");
+        assert_eq!(mapping.external_port(), 1234);
+
+        let add = actions.recv().unwrap();
+        assert!(add.contains("<u:AddPortMapping"));
+        assert!(add.contains("<NewInternalClient>127.0.0.1</NewInternalClient>"));
+        assert!(add.contains(&format!("<NewLeaseDuration>{LEASE}</NewLeaseDuration>")));
+
+        mapping.renew().unwrap();
+        assert!(actions.recv().unwrap().contains("<u:AddPortMapping"));
+
+        mapping.remove().unwrap();
+        let delete = actions.recv().unwrap();
+        assert!(delete.contains("<u:DeletePortMapping"));
+        assert!(delete.contains("<NewExternalPort>1234</NewExternalPort>"));
+    }
+
+    #[test]
+    fn natpmp_mapping_is_leased_renewed_and_removed() {
+        let (natpmp_port, lifetimes) = fake_natpmp();
+        let config = GatewayConfig {
+            gateway: Some(LOCALHOST),
+            natpmp_port,
+            ..Default::default()
+        };
+        let mapping = PortMapping::create_natpmp(&config, 1234).unwrap();
+        assert_eq!(mapping.describe(), "port 1234 forwarded via NAT-PMP");
+        mapping.renew().unwrap();
+        mapping.remove().unwrap();
+        assert_eq!(*lifetimes.lock().unwrap(), vec![LEASE, LEASE, 0]);
+    }
+}
@@ -0,0 +1,195 @@
+use std::{
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use rand::Rng;
+
+const STUN_SERVERS: [&str; 2] = ["stun.l.google.com:19302", "stun.cloudflare.com:3478"];
+const STUN_MAGIC: u32 = 0x2112A442;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How the public address is found out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolver {
+    Stun(Vec<String>),
+    /// Endpoint that answers with the caller's IP as plain text.
+    Http(String),
+    Disabled,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::Stun(STUN_SERVERS.iter().map(|s| s.to_string()).collect())
+    }
+}
+
+impl Resolver {
+    /// Parses `off`, `stun`, `stun:<server>[,<server>...]` or an `http(s)://` URL.
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value == "off" {
+            Some(Self::Disabled)
+        } else if value == "stun" {
+            Some(Self::default())
+        } else if let Some(servers) = value.strip_prefix("stun:") {
+            Some(Self::Stun(servers.split(',').map(str::to_string).collect()))
+        } else if value.starts_with("http://") || value.starts_with("https://") {
+            Some(Self::Http(value.to_string()))
+        } else {
+            None
+        }
+    }
+
+    fn resolve(&self) -> Result<PublicAddr> {
+        match self {
+            Self::Stun(servers) => {
+                let mut last_err = anyhow!("no STUN servers configured");
+                for server in servers {
+                    match stun_request(server) {
+                        Ok(addr) => {
+                            return Ok(PublicAddr {
+                                ip: addr.ip(),
+                                port: Some(addr.port()),
+                            })
+                        }
+                        Err(e) => last_err = anyhow!("{server}: {e}"),
+                    }
+                }
+                Err(last_err)
+            }
+            Self::Http(url) => {
+                let ip = ureq::get(url).timeout(TIMEOUT).call()?.into_string()?;
+                Ok(PublicAddr {
+                    ip: ip.trim().parse()?,
+                    port: None,
+                })
+            }
+            Self::Disabled => Err(anyhow!("disabled")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicAddr {
+    pub ip: IpAddr,
+    /// Port the NAT mapped the STUN query to, when the resolver can tell. That is the
+    /// query's own socket, so the game server's port is only known from port forwarding.
+    pub port: Option<u16>,
+}
+
+impl fmt::Display for PublicAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}", SocketAddr::new(self.ip, port)),
+            None => write!(f, "{}", self.ip),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    NotStarted,
+    Resolving,
+    Done(PublicAddr),
+    Failed(String),
+    Disabled,
+}
+
+lazy_static! {
+    static ref RESOLVER: Mutex<Resolver> = Mutex::new(Resolver::default());
+    static ref LOOKUP: Mutex<Lookup> = Mutex::new(Lookup::NotStarted);
+}
+
+/// Switches resolver, looking the address up again next time it is asked for.
+pub fn set_resolver(resolver: Resolver) {
+    *RESOLVER.lock().unwrap() = resolver;
+    *LOOKUP.lock().unwrap() = Lookup::NotStarted;
+}
+
+/// Returns the public address, resolving it in the background the first time it is asked for.
+pub fn lookup() -> Lookup {
+    let mut lookup = LOOKUP.lock().unwrap();
+    if *lookup == Lookup::NotStarted {
+        let resolver = RESOLVER.lock().unwrap().clone();
+        if resolver == Resolver::Disabled {
+            *lookup = Lookup::Disabled;
+        } else {
+            *lookup = Lookup::Resolving;
+            std::thread::spawn(move || {
+                let result = match resolver.resolve() {
+                    Ok(addr) => Lookup::Done(addr),
+                    Err(e) => Lookup::Failed(e.to_string()),
+                };
+                *LOOKUP.lock().unwrap() = result;
+            });
+        }
+    }
+    lookup.clone()
+}
+
+/// Sends a STUN binding request (RFC 5389) and returns the reflexive address.
+fn stun_request(server: &str) -> Result<SocketAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect(server)?;
+
+    let transaction: [u8; 12] = rand::thread_rng().gen();
+    let mut request = vec![0x00, 0x01, 0x00, 0x00];
+    request.extend(STUN_MAGIC.to_be_bytes());
+    request.extend(transaction);
+    socket.send(&request)?;
+
+    let mut response = [0; 512];
+    let len = socket
+        .recv(&mut response)
+        .map_err(|_| anyhow!("no answer"))?;
+    let response = &response[..len];
+    if len < 20 || response[..2] != [0x01, 0x01] || response[8..20] != transaction {
+        return Err(anyhow!("malformed answer"));
+    }
+
+    let mut attributes = &response[20..];
+    let mut mapped = None;
+    while attributes.len() >= 4 {
+        let kind = u16::from_be_bytes([attributes[0], attributes[1]]);
+        let size = u16::from_be_bytes([attributes[2], attributes[3]]) as usize;
+        let Some(value) = attributes.get(4..4 + size) else {
+            break;
+        };
+        match kind {
+            0x0020 => return parse_address(value, Some(&response[4..20])),
+            0x0001 => mapped = Some(parse_address(value, None)?),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of four bytes
+        attributes = attributes
+            .get(4 + size.next_multiple_of(4)..)
+            .unwrap_or(&[]);
+    }
+    mapped.ok_or(anyhow!("answer has no mapped address"))
+}
+
+/// Decodes a (XOR-)MAPPED-ADDRESS value. `xor` is the magic cookie followed by the transaction id.
+fn parse_address(value: &[u8], xor: Option<&[u8]>) -> Result<SocketAddr> {
+    if value.len() < 8 {
+        return Err(anyhow!("malformed address"));
+    }
+    let mut port = u16::from_be_bytes([value[2], value[3]]);
+    let mut ip = value[4..].to_vec();
+    if let Some(xor) = xor {
+        port ^= (STUN_MAGIC >> 16) as u16;
+        ip.iter_mut().zip(xor).for_each(|(byte, key)| *byte ^= key);
+    }
+
+    let ip = match (value[1], ip.len()) {
+        (0x01, 4) => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(ip).unwrap())),
+        (0x02, 16) => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(ip).unwrap())),
+        _ => return Err(anyhow!("malformed address")),
+    };
+    Ok(SocketAddr::new(ip, port))
+}
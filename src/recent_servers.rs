@@ -0,0 +1,161 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use egui_macroquad::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::{lobby::JoinTarget, settings};
+
+const FILE_NAME: &str = "servers.toml";
+/// Favourites are kept no matter how many servers were joined since.
+const MAX_RECENT: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecentServer {
+    addr: String,
+    relay: Option<String>,
+    name: String,
+    /// Unix time of the last visit.
+    last_seen: u64,
+    #[serde(default)]
+    favourite: bool,
+}
+
+impl RecentServer {
+    fn target(&self) -> JoinTarget {
+        let mut target = JoinTarget::server(&self.addr);
+        target.relay = self.relay.clone();
+        target
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RecentServers {
+    #[serde(default)]
+    servers: Vec<RecentServer>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn ago(time: u64) -> String {
+    match now().saturating_sub(time) {
+        s if s < 60 => "just now".to_string(),
+        s if s < 60 * 60 => format!("{}m ago", s / 60),
+        s if s < 60 * 60 * 24 => format!("{}h ago", s / (60 * 60)),
+        s => format!("{}d ago", s / (60 * 60 * 24)),
+    }
+}
+
+impl RecentServers {
+    pub fn load() -> Self {
+        let Some(text) =
+            settings::config_path(FILE_NAME).and_then(|path| std::fs::read_to_string(path).ok())
+        else {
+            return Self::default();
+        };
+        toml::from_str(&text).unwrap_or_else(|e| {
+            eprintln!("[Servers] ignoring unreadable {FILE_NAME}: {e}");
+            Self::default()
+        })
+    }
+
+    fn save(&self) {
+        let Some(path) = settings::config_path(FILE_NAME) else {
+            return;
+        };
+        let res = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, toml::to_string(self).unwrap()));
+        if let Err(e) = res {
+            eprintln!("[Servers] could not save {}: {e}", path.display());
+        }
+    }
+
+    /// Remembers a visit to a server. Servers on this machine aren't worth remembering.
+    pub fn record(target: &JoinTarget, name: &str) {
+        if target.relay.is_none()
+            && target
+                .addr
+                .parse::<std::net::SocketAddr>()
+                .is_ok_and(|addr| addr.ip().is_loopback())
+        {
+            return;
+        }
+
+        let mut recent = Self::load();
+        let server = match recent
+            .servers
+            .iter()
+            .position(|s| s.addr == target.addr && s.relay == target.relay)
+        {
+            Some(i) => recent.servers.remove(i),
+            None => RecentServer {
+                addr: target.addr.clone(),
+                relay: target.relay.clone(),
+                name: String::new(),
+                last_seen: 0,
+                favourite: false,
+            },
+        };
+        recent.servers.insert(
+            0,
+            RecentServer {
+                name: name.to_string(),
+                last_seen: now(),
+                ..server
+            },
+        );
+
+        let mut kept = 0;
+        recent.servers.retain(|s| {
+            kept += usize::from(!s.favourite);
+            s.favourite || kept <= MAX_RECENT
+        });
+        recent.save();
+    }
+
+    /// Lists favourites first, then the rest by last visit. Returns the server to join, if any.
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<JoinTarget> {
+        if self.servers.is_empty() {
+            ui.label("Servers you join show up here");
+            return None;
+        }
+
+        let mut order = (0..self.servers.len()).collect::<Vec<_>>();
+        order.sort_by_key(|i| !self.servers[*i].favourite);
+
+        let mut join = None;
+        let mut changed = false;
+        egui::Grid::new("recent_servers")
+            .striped(true)
+            .show(ui, |ui| {
+                for i in order {
+                    let server = &mut self.servers[i];
+                    let star = if server.favourite { "★" } else { "☆" };
+                    if ui.small_button(star).on_hover_text("Favourite").clicked() {
+                        server.favourite = !server.favourite;
+                        changed = true;
+                    }
+                    ui.label(&server.name).on_hover_text(match &server.relay {
+                        Some(code) => format!("{} (relay code {code})", server.addr),
+                        None => server.addr.clone(),
+                    });
+                    ui.label(ago(server.last_seen));
+                    if ui.button("Join").clicked() {
+                        join = Some(server.target());
+                    }
+                    ui.end_row();
+                }
+            });
+
+        if changed {
+            self.save();
+        }
+        join
+    }
+}
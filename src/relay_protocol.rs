@@ -0,0 +1,37 @@
+//! Handshake spoken with the relay before a connection turns into a plain
+//! packet stream. Shared with the relay binary through a `#[path]` module.
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::framing;
+
+pub const RELAY_PORT: u16 = 1236;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RelayHello {
+    /// Registers a server, the connection then stays open for control messages.
+    Host,
+    /// Asks to be connected to the server registered under a relay code.
+    Join(String),
+    /// Opened by the server to pick up a waiting session, named by its secret token.
+    Accept(String, u128),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RelayReply {
+    Hosted(String),
+    Incoming(u128),
+    Connected,
+    Error(String),
+}
+
+pub async fn send<T: Serialize>(stream: &mut (impl AsyncWrite + Unpin), msg: &T) -> Result<()> {
+    framing::write_frame(stream, &bincode::serialize(msg)?).await
+}
+
+pub async fn recv<T: DeserializeOwned>(stream: &mut (impl AsyncRead + Unpin)) -> Result<T> {
+    let data = framing::read_frame(stream).await?;
+    Ok(bincode::deserialize(&data)?)
+}
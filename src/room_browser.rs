@@ -0,0 +1,124 @@
+use std::sync::mpsc::Receiver;
+
+use anyhow::{anyhow, Result};
+use egui_macroquad::egui;
+
+use crate::{
+    c2s_packet::C2sPacket,
+    connecting,
+    lobby::JoinTarget,
+    packet_channel,
+    s2c_packet::S2cPacket,
+    server::{RoomInfo, RoomMode},
+    tournament::BracketMode,
+};
+
+enum BrowseStage {
+    Waiting(Receiver<Result<Vec<RoomInfo>>>),
+    Done(Vec<RoomInfo>),
+    Failed(String),
+}
+
+pub struct RoomBrowser {
+    pub target: JoinTarget,
+    pub password_edit: String,
+    stage: BrowseStage,
+}
+
+impl RoomBrowser {
+    pub fn query(target: JoinTarget) -> Self {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let query_target = target.clone();
+        tokio::spawn(async move {
+            let _ = tx.send(Self::fetch(query_target).await);
+        });
+
+        Self {
+            target,
+            password_edit: String::new(),
+            stage: BrowseStage::Waiting(rx),
+        }
+    }
+
+    async fn fetch(target: JoinTarget) -> Result<Vec<RoomInfo>> {
+        let timeout = tokio::time::Duration::from_secs(3);
+        let stream = connecting::open(&target)
+            .await
+            .map_err(|e| anyhow!("{e}"))?;
+        let (mut rx, tx) = packet_channel::async_channel::<C2sPacket, S2cPacket>(stream);
+        tx.send(C2sPacket::QueryRooms).await?;
+
+        match tokio::time::timeout(timeout, rx.recv()).await? {
+            Some(Ok(S2cPacket::SyncRoomList(rooms))) => Ok(rooms),
+            Some(Err(e)) => Err(e),
+            _ => Err(anyhow!("the server did not send a room list")),
+        }
+    }
+
+    /// Draws the room list, returning the code of the room the player wants to join.
+    pub fn ui(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        if let BrowseStage::Waiting(rx) = &self.stage {
+            if let Ok(result) = rx.try_recv() {
+                self.stage = match result {
+                    Ok(rooms) => BrowseStage::Done(rooms),
+                    Err(e) => BrowseStage::Failed(e.to_string()),
+                };
+            }
+        }
+
+        let mut join = None;
+        match &self.stage {
+            BrowseStage::Waiting(_) => {
+                ui.label("Loading rooms...");
+            }
+            BrowseStage::Failed(e) => {
+                ui.label(format!("Could not reach {}: {e}", self.target.addr));
+            }
+            BrowseStage::Done(rooms) if rooms.is_empty() => {
+                ui.label("No rooms yet");
+            }
+            BrowseStage::Done(rooms) => {
+                egui::Grid::new("browser").striped(true).show(ui, |ui| {
+                    for room in rooms {
+                        if room_row(ui, room) {
+                            join = Some(room.code.clone());
+                        }
+                    }
+                });
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Password");
+            ui.text_edit_singleline(&mut self.password_edit);
+        });
+        if ui.button("Refresh").clicked() {
+            *self = Self::query(self.target.clone());
+        }
+
+        join
+    }
+}
+
+/// Draws one row of a room list grid, returning whether "Join" was clicked.
+pub fn room_row(ui: &mut egui::Ui, room: &RoomInfo) -> bool {
+    let name = if room.locked {
+        format!("🔒 {}", room.name)
+    } else {
+        room.name.clone()
+    };
+    ui.label(name);
+    ui.label(format!("{}/{}", room.players, room.max_players));
+    ui.label(match room.mode {
+        RoomMode::Waiting => "Waiting",
+        RoomMode::Classic => "In game",
+        RoomMode::Tournament(BracketMode::Survivors) => "Tournament",
+        RoomMode::Tournament(BracketMode::Losers) => "Loser's bracket",
+    });
+
+    let full = room.players >= room.max_players;
+    let clicked = ui.add_enabled(!full, egui::Button::new("Join")).clicked();
+    ui.end_row();
+    clicked
+}
@@ -1,12 +1,24 @@
 use serde::{Deserialize, Serialize};
 
-use crate::server::Game;
+use crate::{
+    server::{Game, RoomInfo, ServerInfo},
+    sound_event::SoundEvent,
+    sound_pack,
+    tournament::Bracket,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum S2cPacket {
+    SyncServerInfo(ServerInfo),
+    SyncRoomList(Vec<RoomInfo>),
+    SyncRoom(RoomInfo),
+    JoinRoomFailed(String),
     SyncPlayerList(bool, Vec<String>),
     SyncGame(Game),
-    PlaySound(String),
+    SyncBracket(Bracket),
+    PlaySound(SoundEvent),
+    /// Part of a sound pack asset. Empty if the server can't send it.
+    AssetChunk(sound_pack::Hash, u32, Vec<u8>),
     KillYourselfNow,
     Disconnect(String),
 }
@@ -1,20 +1,54 @@
-use std::{collections::HashSet, net::SocketAddr, time::Instant};
+use std::{
+    collections::HashSet,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
 
+use anyhow::{anyhow, Result};
 use egui_macroquad::egui::epaint::ahash::{HashMap, HashMapExt};
 use rand::{seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpStream,
-    sync::mpsc::{self, Receiver, Sender},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        watch,
+    },
 };
 use tokio_util::sync::CancellationToken;
 
-use crate::{c2s_packet::C2sPacket, packet_channel, s2c_packet::S2cPacket};
+use crate::{
+    c2s_packet::{C2sPacket, PROTOCOL_VERSION},
+    discovery::{self, ServerAnnouncement},
+    packet_channel::{self, DecodeError},
+    port_mapping::{self, GatewayConfig, MappingStatus, PortMapping},
+    relay_protocol::{self, RelayHello, RelayReply},
+    s2c_packet::S2cPacket,
+    sound_event::SoundEvent,
+    sound_pack::{HostedPack, SoundPackManifest},
+    tournament::{Bracket, BracketMode},
+};
 
 pub const PORT: u16 = 1234;
+pub const BIND_ADDR: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Set while a server started by this process is accepting connections.
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+pub fn listening() -> bool {
+    LISTENING.load(Ordering::Relaxed)
+}
+const ROOM_CODE_CHARS: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const ROOM_CODE_LEN: usize = 5;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Game {
+    pub table: usize,
     pub queue: Vec<String>,
     pub progress: HashMap<char, u8>,
     pub fired: HashSet<char>,
@@ -24,10 +58,70 @@ pub struct Game {
     trigger_key: char,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoomSettings {
+    pub name: String,
+    pub max_players: usize,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoomMode {
+    Waiting,
+    Classic,
+    Tournament(BracketMode),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoomInfo {
+    pub code: String,
+    pub name: String,
+    pub players: usize,
+    pub max_players: usize,
+    pub mode: RoomMode,
+    pub locked: bool,
+}
+
+pub struct ServerConfig {
+    pub dedicated: bool,
+    pub name: String,
+    /// Relay to register with so players can join without port forwarding.
+    pub relay: Option<String>,
+    /// Ask the gateway to forward `PORT` while the server is running.
+    pub port_mapping: Option<GatewayConfig>,
+    /// Folder of sounds to send to players, see `HostedPack::load`.
+    pub sound_pack: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub name: String,
+    /// Address of the relay this server is registered with, under `relay_code`.
+    pub relay_addr: Option<String>,
+    pub relay_code: Option<String>,
+    pub sound_pack: Option<Box<SoundPackManifest>>,
+}
+
 #[derive(Debug)]
 pub struct Server {
+    dedicated: bool,
+    lobby: Vec<PacketPlayer>,
+    rooms: Vec<Room>,
+    owner_dying: Arc<OwnerDying>,
+}
+
+/// Set to the owner's address right before they are punished, so every other
+/// player on the server hears it is going down, whichever room they are in.
+type OwnerDying = watch::Sender<Option<SocketAddr>>;
+
+#[derive(Debug)]
+struct Room {
+    code: String,
+    settings: RoomSettings,
     players: Vec<PacketPlayer>,
-    game: Option<Game>,
+    tables: Vec<Game>,
+    tournament: Option<Bracket>,
+    owner_dying: Arc<OwnerDying>,
 }
 
 #[derive(Debug)]
@@ -50,6 +144,9 @@ struct PacketPlayer {
     reciever: Receiver<C2sMessage>,
     name: String,
     host: bool,
+    /// Whether this player is running the server, in which case blue
+    /// screening them takes everyone else down too.
+    owner: bool,
 }
 
 impl PacketPlayer {
@@ -65,12 +162,40 @@ impl PacketPlayer {
 impl Server {
     async fn handle_client(
         stream: TcpStream,
+        sock: SocketAddr,
+        mut owner_dying: watch::Receiver<Option<SocketAddr>>,
         mut in_rx: Receiver<S2cMessage>,
         out_tx: Sender<C2sMessage>,
+        room_list: Arc<Mutex<Vec<RoomInfo>>>,
+        sound_pack: Arc<Option<HostedPack>>,
     ) {
         let (mut rx, tx) = packet_channel::async_channel::<S2cPacket, C2sPacket>(stream);
-        let Some(Ok(C2sPacket::CreatePlayer(name))) = rx.recv().await else {
-            return;
+        let name = match rx.recv().await {
+            Some(Ok(C2sPacket::CreatePlayer(PROTOCOL_VERSION, name))) => name,
+            Some(Ok(C2sPacket::CreatePlayer(version, _))) => {
+                let msg = format!(
+                    "Version mismatch: the server speaks protocol {PROTOCOL_VERSION}, you speak {version}"
+                );
+                let _ = tx.send(S2cPacket::Disconnect(msg)).await;
+                let _ = out_tx.send(C2sMessage::PlayerDisconnect).await;
+                return;
+            }
+            Some(Ok(C2sPacket::QueryRooms)) => {
+                let room_list = room_list.lock().unwrap().clone();
+                let _ = tx.send(S2cPacket::SyncRoomList(room_list)).await;
+                let _ = out_tx.send(C2sMessage::PlayerDisconnect).await;
+                return;
+            }
+            Some(Err(e)) if e.is::<DecodeError>() => {
+                let msg = "Version mismatch: the server could not read your client's packets";
+                let _ = tx.send(S2cPacket::Disconnect(msg.to_string())).await;
+                let _ = out_tx.send(C2sMessage::PlayerDisconnect).await;
+                return;
+            }
+            _ => {
+                let _ = out_tx.send(C2sMessage::PlayerDisconnect).await;
+                return;
+            }
         };
 
         out_tx
@@ -78,7 +203,26 @@ impl Server {
             .await
             .unwrap();
         loop {
+            if owner_dying.has_changed().unwrap_or(false)
+                && owner_dying
+                    .borrow_and_update()
+                    .is_some_and(|owner| owner != sock)
+            {
+                let msg = "The host has died".to_string();
+                let _ = tx.send(S2cPacket::Disconnect(msg)).await;
+                break;
+            }
             match rx.try_recv() {
+                Ok(Ok(C2sPacket::RequestAssetChunk(sha256, offset))) => {
+                    // Answered here so downloads don't hold up the game loop
+                    let chunk = sound_pack
+                        .as_ref()
+                        .as_ref()
+                        .map_or(vec![], |pack| pack.chunk(&sha256, offset));
+                    let Ok(_) = tx.send(S2cPacket::AssetChunk(sha256, offset, chunk)).await else {
+                        break;
+                    };
+                }
                 Ok(Ok(packet)) => {
                     let Ok(_) = out_tx.send(C2sMessage::Packet(packet)).await else {
                         break;
@@ -126,18 +270,160 @@ impl Server {
         println!("closing connection");
     }
 
-    pub async fn start() {
-        let tcp = tokio::net::TcpListener::bind(format!("0.0.0.0:{PORT}"))
+    fn accept(
+        stream: TcpStream,
+        sock: SocketAddr,
+        owner_dying: &OwnerDying,
+        pp_tx: &std::sync::mpsc::Sender<PacketPlayer>,
+        room_list: &Arc<Mutex<Vec<RoomInfo>>>,
+        sound_pack: &Arc<Option<HostedPack>>,
+    ) {
+        let (tx, rx) = mpsc::channel(1024);
+        let (reply_tx, reply_rx) = mpsc::channel(1024);
+        let pp = PacketPlayer {
+            sock_addr: sock,
+            sender: tx,
+            reciever: reply_rx,
+            name: "???".to_string(),
+            host: false,
+            owner: false,
+        };
+        pp_tx.send(pp).unwrap();
+        let room_list = room_list.clone();
+        let sound_pack = sound_pack.clone();
+        let owner_dying = owner_dying.subscribe();
+        tokio::spawn(async move {
+            Self::handle_client(
+                stream,
+                sock,
+                owner_dying,
+                rx,
+                reply_tx,
+                room_list,
+                sound_pack,
+            )
+            .await;
+        });
+    }
+
+    async fn serve_relay(
+        relay: &str,
+        info: &Mutex<ServerInfo>,
+        owner_dying: &OwnerDying,
+        pp_tx: &std::sync::mpsc::Sender<PacketPlayer>,
+        room_list: &Arc<Mutex<Vec<RoomInfo>>>,
+        sound_pack: &Arc<Option<HostedPack>>,
+    ) -> Result<()> {
+        let mut control = TcpStream::connect(relay).await?;
+        relay_protocol::send(&mut control, &RelayHello::Host).await?;
+        let RelayReply::Hosted(code) = relay_protocol::recv(&mut control).await? else {
+            return Err(anyhow!("unexpected reply from relay"));
+        };
+        println!("Registered with relay as {code}");
+        {
+            let mut info = info.lock().unwrap();
+            info.relay_addr = Some(relay.to_string());
+            info.relay_code = Some(code.clone());
+        }
+
+        loop {
+            let RelayReply::Incoming(session) = relay_protocol::recv(&mut control).await? else {
+                continue;
+            };
+
+            let mut stream = TcpStream::connect(relay).await?;
+            relay_protocol::send(&mut stream, &RelayHello::Accept(code.clone(), session)).await?;
+            // Everyone relayed shares the relay's address, so tell them apart by our end instead
+            let sock = stream.local_addr()?;
+            Self::accept(stream, sock, owner_dying, pp_tx, room_list, sound_pack);
+        }
+    }
+
+    pub async fn start(config: ServerConfig) {
+        let tcp = tokio::net::TcpListener::bind((BIND_ADDR, PORT))
             .await
             .unwrap();
+        LISTENING.store(true, Ordering::Relaxed);
         println!("Server started");
         let mut s = Self {
-            players: vec![],
-            game: None,
+            dedicated: config.dedicated,
+            lobby: vec![],
+            rooms: vec![],
+            owner_dying: Arc::new(watch::channel(None).0),
         };
         let (pp_tx, pp_rx) = std::sync::mpsc::channel();
         let token = CancellationToken::new();
         let closed = token.clone();
+        let shared_room_list = Arc::new(Mutex::new(vec![]));
+        let accept_room_list = shared_room_list.clone();
+        let announcement = Arc::new(Mutex::new(ServerAnnouncement {
+            name: config.name.clone(),
+            port: PORT,
+            ..Default::default()
+        }));
+        tokio::spawn(discovery::announce(announcement.clone(), token.clone()));
+        if s.dedicated {
+            let shutdown = token.clone();
+            tokio::spawn(async move {
+                let _ = tokio::signal::ctrl_c().await;
+                shutdown.cancel();
+            });
+        }
+        let sound_pack = config
+            .sound_pack
+            .and_then(|dir| match HostedPack::load(&dir) {
+                Ok(pack) => {
+                    println!(
+                        "[Sound pack] sharing {} ({} sounds)",
+                        pack.manifest.name,
+                        pack.manifest.assets.len()
+                    );
+                    Some(pack)
+                }
+                Err(e) => {
+                    eprintln!("[Sound pack] could not load {}: {e}", dir.display());
+                    None
+                }
+            });
+        let server_info = Arc::new(Mutex::new(ServerInfo {
+            name: config.name,
+            relay_addr: None,
+            relay_code: None,
+            sound_pack: sound_pack
+                .as_ref()
+                .map(|pack| Box::new(pack.manifest.clone())),
+        }));
+        let sound_pack = Arc::new(sound_pack);
+
+        let mapping = config
+            .port_mapping
+            .map(|gateway| tokio::spawn(Self::keep_port_mapped(gateway, token.clone())));
+
+        if let Some(relay) = config.relay {
+            let info = server_info.clone();
+            let pp_tx = pp_tx.clone();
+            let room_list = shared_room_list.clone();
+            let sound_pack = sound_pack.clone();
+            let owner_dying = s.owner_dying.clone();
+            let closed = token.clone();
+            tokio::spawn(async move {
+                let serve =
+                    Self::serve_relay(&relay, &info, &owner_dying, &pp_tx, &room_list, &sound_pack);
+                tokio::select! {
+                    res = serve => {
+                        if let Err(e) = res {
+                            eprintln!("[Relay] lost connection to {relay}: {e}");
+                        }
+                    }
+                    _ = closed.cancelled() => {}
+                }
+                let mut info = info.lock().unwrap();
+                info.relay_addr = None;
+                info.relay_code = None;
+            });
+        }
+
+        let owner_dying = s.owner_dying.clone();
         tokio::spawn(async move {
             loop {
                 let (stream, sock) = tokio::select! {
@@ -149,37 +435,34 @@ impl Server {
                         break;
                     }
                 };
-                let (tx, rx) = mpsc::channel(1024);
-                let (reply_tx, reply_rx) = mpsc::channel(1024);
-                let pp = PacketPlayer {
-                    sock_addr: sock,
-                    sender: tx,
-                    reciever: reply_rx,
-                    name: "???".to_string(),
-                    host: false,
-                };
-                pp_tx.send(pp).unwrap();
-                tokio::spawn(async move {
-                    Self::handle_client(stream, rx, reply_tx).await;
-                });
+                Self::accept(
+                    stream,
+                    sock,
+                    &owner_dying,
+                    &pp_tx,
+                    &accept_room_list,
+                    &sound_pack,
+                );
             }
 
             tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
             println!("Server stopped");
         });
         let mut had_players = false;
+        let mut room_list = vec![];
+        let mut last_server_info = ServerInfo::default();
         loop {
             if let Ok(new_player) = pp_rx.try_recv() {
                 let sock = new_player.sock_addr;
-                s.players.push(new_player);
+                s.lobby.push(new_player);
                 s.player_joined(sock).await;
             }
 
             let mut pack_queue = vec![];
             let mut remove_queue = vec![];
-            let mut should_resync_playerlist = false;
+            let mut should_sync_room_list = false;
 
-            for pl in &mut s.players {
+            for pl in &mut s.lobby {
                 if let Ok(pack) = pl.reciever.try_recv() {
                     let sock = pl.sock_addr;
                     match pack {
@@ -187,7 +470,7 @@ impl Server {
                             pack_queue.push((sock, packet));
                         }
                         C2sMessage::PlayerConnect(name) => {
-                            let host = !had_players;
+                            let owner = !had_players && !s.dedicated;
                             had_players = true;
                             let name = name.trim();
                             if name.is_empty() {
@@ -199,11 +482,11 @@ impl Server {
                                 continue;
                             }
                             pl.name = name.to_string();
-                            pl.host = host;
-                            should_resync_playerlist = true;
+                            pl.owner = owner;
+                            should_sync_room_list = true;
                         }
                         C2sMessage::PlayerDisconnect => {
-                            if pl.host {
+                            if pl.owner {
                                 token.cancel();
                             }
                             remove_queue.push((sock, None));
@@ -213,43 +496,221 @@ impl Server {
             }
 
             for (sock, reason) in remove_queue {
-                s.remove_player(sock, reason, None).await;
+                s.remove_lobby_player(sock, reason).await;
             }
 
-            if should_resync_playerlist {
-                s.sync_playerlist().await;
+            for (sock, packet) in pack_queue {
+                match packet {
+                    C2sPacket::CreateRoom(settings) => s.create_room(sock, settings).await,
+                    C2sPacket::JoinRoom(code, password) => s.join_room(sock, &code, password).await,
+                    _ => { /* only valid once inside a room */ }
+                }
             }
 
-            for (sock, packet) in pack_queue {
-                s.on_packet(sock, packet).await;
+            for room in &mut s.rooms {
+                room.update(&token).await;
+            }
+            s.rooms.retain(|r| !r.players.is_empty());
+
+            let new_room_list = s.room_list();
+            if should_sync_room_list || new_room_list != room_list {
+                room_list = new_room_list;
+                *shared_room_list.lock().unwrap() = room_list.clone();
+                s.sync_room_list(&room_list).await;
             }
 
-            let new_playercount = s.players.len();
+            let new_server_info = server_info.lock().unwrap().clone();
+            if should_sync_room_list || new_server_info != last_server_info {
+                last_server_info = new_server_info;
+                s.sync_server_info(&last_server_info).await;
+            }
+
+            let new_playercount =
+                s.lobby.len() + s.rooms.iter().map(|r| r.players.len()).sum::<usize>();
+            {
+                let mut announcement = announcement.lock().unwrap();
+                announcement.players = new_playercount;
+                announcement.in_game = s.rooms.iter().any(|r| !r.tables.is_empty());
+            }
 
             tokio::task::yield_now().await;
 
-            if new_playercount == 0 && had_players {
+            if !s.dedicated && new_playercount == 0 && had_players {
                 token.cancel();
 
                 tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
                 break;
             }
-            if let Some(game) = s.game.as_mut() {
-                if let Some(last_frame) = game.last_frame {
-                    if Instant::now().duration_since(last_frame).as_millis() >= 50 {
-                        game.last_frame = Some(Instant::now());
-                        s.tick().await;
-                    }
-                }
+            if s.dedicated && token.is_cancelled() {
+                break;
+            }
+        }
+
+        if let Some(mapping) = mapping {
+            let _ = mapping.await;
+        }
+        LISTENING.store(false, Ordering::Relaxed);
+    }
+
+    /// Maps `PORT` on the gateway and renews the lease until `token` is cancelled.
+    async fn keep_port_mapped(gateway: GatewayConfig, token: CancellationToken) {
+        *port_mapping::STATUS.lock().unwrap() = MappingStatus::Pending;
+        let mapping = tokio::task::spawn_blocking(move || PortMapping::create(&gateway, PORT))
+            .await
+            .unwrap();
+        let mapping = match mapping {
+            Ok(mapping) => {
+                println!("[Port mapping] {}", mapping.describe());
+                *port_mapping::STATUS.lock().unwrap() = MappingStatus::Mapped {
+                    external_port: mapping.external_port(),
+                    description: mapping.describe(),
+                };
+                Arc::new(mapping)
+            }
+            Err(e) => {
+                eprintln!("[Port mapping] failed: {e}");
+                *port_mapping::STATUS.lock().unwrap() = MappingStatus::Failed(e.to_string());
+                return;
+            }
+        };
+
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = tokio::time::sleep(port_mapping::RENEW_INTERVAL) => {}
+            }
+            let renewing = mapping.clone();
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || renewing.renew()).await {
+                eprintln!("[Port mapping] could not renew mapping: {e}");
+            }
+        }
+
+        let _ = tokio::task::spawn_blocking(move || {
+            if let Err(e) = mapping.remove() {
+                eprintln!("[Port mapping] could not remove mapping: {e}");
+            }
+        })
+        .await;
+        *port_mapping::STATUS.lock().unwrap() = MappingStatus::Off;
+    }
+
+    async fn player_joined(&mut self, _addr: SocketAddr) {}
+
+    fn room_list(&self) -> Vec<RoomInfo> {
+        self.rooms.iter().map(Room::info).collect()
+    }
+
+    async fn sync_room_list(&mut self, room_list: &[RoomInfo]) {
+        for pl in &mut self.lobby {
+            pl.send_packet(S2cPacket::SyncRoomList(room_list.to_vec()))
+                .await;
+        }
+    }
+
+    async fn sync_server_info(&mut self, info: &ServerInfo) {
+        let players = self
+            .lobby
+            .iter_mut()
+            .chain(self.rooms.iter_mut().flat_map(|r| r.players.iter_mut()));
+        for pl in players {
+            pl.send_packet(S2cPacket::SyncServerInfo(info.clone()))
+                .await;
+        }
+    }
+
+    async fn remove_lobby_player(&mut self, addr: SocketAddr, reason: Option<String>) {
+        let Some(player) = self.lobby.iter_mut().find(|p| p.sock_addr == addr) else {
+            return;
+        };
+
+        player
+            .disconnect(reason.unwrap_or("You have been disconnected".to_string()))
+            .await;
+        self.lobby.retain(|p| p.sock_addr != addr);
+    }
+
+    fn new_room_code(&self) -> String {
+        loop {
+            let code = (0..ROOM_CODE_LEN)
+                .map(|_| *ROOM_CODE_CHARS.choose(&mut rand::thread_rng()).unwrap() as char)
+                .collect::<String>();
+            if !self.rooms.iter().any(|r| r.code == code) {
+                return code;
             }
         }
     }
+
+    async fn create_room(&mut self, addr: SocketAddr, mut settings: RoomSettings) {
+        let Some(player) = self.lobby.iter().find(|p| p.sock_addr == addr) else {
+            return;
+        };
+
+        settings.name = settings.name.trim().to_string();
+        if settings.name.is_empty() {
+            settings.name = format!("{}'s room", player.name);
+        }
+        settings.max_players = settings.max_players.clamp(2, 32);
+        settings.password = settings.password.filter(|p| !p.is_empty());
+
+        let code = self.new_room_code();
+        let password = settings.password.clone();
+        self.rooms.push(Room {
+            code: code.clone(),
+            settings,
+            players: vec![],
+            tables: vec![],
+            tournament: None,
+            owner_dying: self.owner_dying.clone(),
+        });
+        self.join_room(addr, &code, password).await;
+    }
+
+    async fn join_room(&mut self, addr: SocketAddr, code: &str, password: Option<String>) {
+        let Some(idx) = self.lobby.iter().position(|p| p.sock_addr == addr) else {
+            return;
+        };
+        let code = code.trim().to_uppercase();
+        let Some(room) = self.rooms.iter_mut().find(|r| r.code == code) else {
+            self.lobby[idx]
+                .send_packet(S2cPacket::JoinRoomFailed(format!(
+                    "There is no room with the code {code}"
+                )))
+                .await;
+            return;
+        };
+        let error = if room.players.len() >= room.settings.max_players {
+            Some("That room is full")
+        } else if room.settings.password.is_some() && room.settings.password != password {
+            Some("Wrong password")
+        } else if room.playing() {
+            // Players are only seated when a game starts
+            Some("That room is already playing, try again after the game")
+        } else if room.players.iter().any(|p| p.name == self.lobby[idx].name) {
+            // Turns are tracked by name, so two of them would share a seat
+            Some("Someone in that room already has your name")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            self.lobby[idx]
+                .send_packet(S2cPacket::JoinRoomFailed(error.to_string()))
+                .await;
+            return;
+        }
+
+        let mut player = self.lobby.remove(idx);
+        player.host = room.players.is_empty();
+        room.players.push(player);
+        room.sync_room().await;
+        room.sync_playerlist().await;
+    }
 }
 
 impl Game {
-    fn new(mut players: Vec<String>) -> Self {
+    fn new(table: usize, mut players: Vec<String>) -> Self {
         players.shuffle(&mut rand::thread_rng());
         Self {
+            table,
             progress: HashMap::new(),
             queue: players,
             last_frame: Some(Instant::now()),
@@ -258,8 +719,8 @@ impl Game {
         }
     }
 
-    fn current(&self) -> String {
-        self.queue[0].clone()
+    fn current(&self) -> Option<String> {
+        self.queue.first().cloned()
     }
 
     fn advance(&mut self) -> String {
@@ -269,7 +730,79 @@ impl Game {
     }
 }
 
-impl Server {
+impl Room {
+    /// A classic game stays on its table after the winner is left, so it only counts until then.
+    fn playing(&self) -> bool {
+        self.tournament.is_some() || self.tables.iter().any(|g| g.queue.len() > 1)
+    }
+
+    fn info(&self) -> RoomInfo {
+        let mode = if self.tables.is_empty() {
+            RoomMode::Waiting
+        } else if let Some(bracket) = &self.tournament {
+            RoomMode::Tournament(bracket.mode)
+        } else {
+            RoomMode::Classic
+        };
+        RoomInfo {
+            code: self.code.clone(),
+            name: self.settings.name.clone(),
+            players: self.players.len(),
+            max_players: self.settings.max_players,
+            mode,
+            locked: self.settings.password.is_some(),
+        }
+    }
+
+    async fn update(&mut self, token: &CancellationToken) {
+        let mut pack_queue = vec![];
+        let mut remove_queue = vec![];
+
+        for pl in &mut self.players {
+            if let Ok(pack) = pl.reciever.try_recv() {
+                let sock = pl.sock_addr;
+                match pack {
+                    C2sMessage::Packet(packet) => {
+                        pack_queue.push((sock, packet));
+                    }
+                    C2sMessage::PlayerConnect(_) => { /* handled before joining a room */ }
+                    C2sMessage::PlayerDisconnect => {
+                        if pl.owner {
+                            token.cancel();
+                        }
+                        remove_queue.push(sock);
+                    }
+                }
+            }
+        }
+
+        for sock in remove_queue {
+            self.remove_player(sock, None, None).await;
+        }
+
+        for (sock, packet) in pack_queue {
+            self.on_packet(sock, packet).await;
+        }
+
+        for table in 0..self.tables.len() {
+            let game = &mut self.tables[table];
+            if let Some(last_frame) = game.last_frame {
+                if Instant::now().duration_since(last_frame).as_millis() >= 50 {
+                    game.last_frame = Some(Instant::now());
+                    self.tick(table).await;
+                }
+            }
+        }
+        self.update_tournament().await;
+    }
+
+    async fn sync_room(&mut self) {
+        let info = self.info();
+        for pl in &mut self.players {
+            pl.send_packet(S2cPacket::SyncRoom(info.clone())).await;
+        }
+    }
+
     async fn sync_playerlist(&mut self) {
         let playerlist = self
             .players
@@ -282,6 +815,55 @@ impl Server {
         }
     }
 
+    async fn sync_bracket(&mut self) {
+        let Some(bracket) = self.tournament.clone() else {
+            return;
+        };
+        for pl in &mut self.players {
+            pl.send_packet(S2cPacket::SyncBracket(bracket.clone()))
+                .await;
+        }
+    }
+
+    /// Sends a packet to everyone seated at `table`. Outside of a tournament
+    /// every player sits at the one table.
+    async fn broadcast_table(&mut self, table: usize, pack: S2cPacket) {
+        let bracket = self.tournament.as_ref();
+        for pl in &mut self.players {
+            if bracket.is_none_or(|b| b.table_of(&pl.name) == Some(table)) {
+                pl.send_packet(pack.clone()).await;
+            }
+        }
+    }
+
+    async fn play_sound_for(&mut self, addr: SocketAddr, event: SoundEvent) {
+        if let Some(pl) = self.player_mut(addr) {
+            pl.send_packet(S2cPacket::PlaySound(event)).await;
+        }
+    }
+
+    /// Lets whoever is first in the queue know it's their turn.
+    async fn announce_turn(&mut self, game: &Game) {
+        if let Some(addr) = game.current().and_then(|name| self.addr_from_name(&name)) {
+            self.play_sound_for(addr, SoundEvent::TurnStart).await;
+        }
+    }
+
+    async fn sync_table(&mut self, game: &Game) {
+        self.broadcast_table(game.table, S2cPacket::SyncGame(game.clone()))
+            .await;
+    }
+
+    fn table_open(&self, table: usize) -> bool {
+        table < self.tables.len() && self.tournament.as_ref().is_none_or(|b| b.is_open(table))
+    }
+
+    fn table_of(&self, name: &str) -> Option<usize> {
+        self.tables
+            .iter()
+            .position(|g| g.queue.iter().any(|q| q == name))
+    }
+
     fn player_mut(&mut self, addr: SocketAddr) -> Option<&mut PacketPlayer> {
         self.players.iter_mut().find(|p| p.sock_addr == addr)
     }
@@ -293,8 +875,6 @@ impl Server {
             .map(|p| p.name.to_string())
     }
 
-    async fn player_joined(&mut self, _addr: SocketAddr) {}
-
     fn addr_from_name(&mut self, name: &str) -> Option<SocketAddr> {
         self.players
             .iter_mut()
@@ -302,27 +882,34 @@ impl Server {
             .map(|p| p.sock_addr)
     }
 
-    async fn remove_player(
-        &mut self,
-        addr: SocketAddr,
-        reason: Option<String>,
-        game: Option<&mut Game>,
-    ) {
+    /// Knocks the player at `addr` out of the tournament and takes them off their table.
+    async fn leave_table(&mut self, addr: SocketAddr, game: Option<&mut Game>) {
         if let Some(name) = self.player_name(addr).clone() {
+            if let Some(bracket) = self.tournament.as_mut() {
+                bracket.knock_out(&name);
+            }
+
             if let Some(game) = game {
                 game.queue.retain(|qname| qname != &name);
-
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::SyncGame(game.clone())).await;
-                }
-            } else if let Some(game) = self.game.as_mut() {
+                self.sync_table(game).await;
+            } else if let Some(table) = self.table_of(&name) {
+                let game = &mut self.tables[table];
                 game.queue.retain(|qname| qname != &name);
-
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::SyncGame(game.clone())).await;
-                }
+                let game = game.clone();
+                self.sync_table(&game).await;
             }
+
+            self.sync_bracket().await;
         }
+    }
+
+    async fn remove_player(
+        &mut self,
+        addr: SocketAddr,
+        reason: Option<String>,
+        game: Option<&mut Game>,
+    ) {
+        self.leave_table(addr, game).await;
 
         let Some(player) = self.player_mut(addr) else {
             return;
@@ -336,66 +923,225 @@ impl Server {
             .disconnect(reason.unwrap_or("You have been disconnected".to_string()))
             .await;
         self.players.retain(|p| p.sock_addr != addr);
+        if !self.players.iter().any(|p| p.host) {
+            if let Some(new_host) = self.players.first_mut() {
+                new_host.host = true;
+            }
+        }
         self.sync_playerlist().await;
     }
 
+    /// Blue screens the player at `addr`. If that player is running the server,
+    /// everyone else in every room is sent away first since it goes down with them.
+    async fn punish(&mut self, addr: SocketAddr, reason: &str, mut game: Option<&mut Game>) {
+        if self.player_mut(addr).is_some_and(|p| p.owner) {
+            self.owner_dying.send_replace(Some(addr));
+            for sock in self
+                .players
+                .iter()
+                .filter(|p| !p.owner)
+                .map(|p| p.sock_addr)
+                .collect::<Vec<_>>()
+            {
+                self.remove_player(
+                    sock,
+                    Some("The host has died".to_string()),
+                    game.as_deref_mut(),
+                )
+                .await;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        }
+
+        if let Some(pl) = self.player_mut(addr) {
+            pl.send_packet(S2cPacket::KillYourselfNow).await;
+        }
+
+        self.remove_player(addr, Some(reason.to_string()), game)
+            .await;
+    }
+
+    /// Blue screens a player knocked out of a tournament but keeps them in the room, so
+    /// they can follow the bracket until it is decided.
+    async fn eliminate(&mut self, addr: SocketAddr, game: &mut Game) {
+        if self.player_mut(addr).is_some_and(|p| p.owner) {
+            // Their server is about to go down, so there is nothing left to watch
+            self.punish(addr, "You lost.", Some(game)).await;
+            return;
+        }
+        if let Some(pl) = self.player_mut(addr) {
+            pl.send_packet(S2cPacket::KillYourselfNow).await;
+        }
+        self.leave_table(addr, Some(game)).await;
+    }
+
+    async fn start_tables(&mut self) {
+        for game in self.tables.clone() {
+            self.sync_table(&game).await;
+            self.announce_turn(&game).await;
+        }
+    }
+
+    async fn update_tournament(&mut self) {
+        let Some(bracket) = self.tournament.as_mut() else {
+            return;
+        };
+
+        let mut changed = false;
+        for (table, game) in self.tables.iter().enumerate() {
+            if bracket.is_open(table) && game.queue.len() <= 1 {
+                bracket.finish_table(table, game.queue.clone());
+                changed = true;
+            }
+        }
+
+        if !bracket.round_finished() {
+            if changed {
+                self.sync_bracket().await;
+            }
+            return;
+        }
+
+        let advancing = bracket.advancing();
+        if advancing.len() > 1 {
+            bracket.next_round();
+            self.tables = bracket
+                .round()
+                .iter()
+                .enumerate()
+                .map(|(table, t)| Game::new(table, t.players.clone()))
+                .collect();
+            self.sync_bracket().await;
+            self.start_tables().await;
+            return;
+        }
+
+        let mode = bracket.mode;
+        self.sync_bracket().await;
+        self.tournament = None;
+        self.tables.clear();
+
+        let Some(last) = advancing.first() else {
+            return;
+        };
+        let last_addr = self.addr_from_name(last);
+        let result = match mode {
+            BracketMode::Survivors => format!("{last} won the tournament"),
+            BracketMode::Losers => format!("{last} lost the tournament"),
+        };
+        for sock in self
+            .players
+            .iter()
+            .map(|p| p.sock_addr)
+            .filter(|sock| Some(*sock) != last_addr)
+            .collect::<Vec<_>>()
+        {
+            self.remove_player(sock, Some(result.clone()), None).await;
+        }
+
+        let Some(last_addr) = last_addr else {
+            return;
+        };
+        match mode {
+            BracketMode::Survivors => {
+                self.play_sound_for(last_addr, SoundEvent::Win).await;
+                self.remove_player(
+                    last_addr,
+                    Some("You won the tournament :)".to_string()),
+                    None,
+                )
+                .await;
+            }
+            BracketMode::Losers => {
+                self.punish(last_addr, "You lost the tournament.", None)
+                    .await;
+            }
+        }
+    }
+
     async fn on_packet(&mut self, addr: SocketAddr, pack: C2sPacket) {
         let Some(pl) = self.player_mut(addr) else {
             return;
         };
         let host = pl.host;
         match pack {
-            C2sPacket::CreatePlayer(_) => { /* should be handled for us */ }
+            C2sPacket::CreatePlayer(..) | C2sPacket::RequestAssetChunk(..) => {
+                /* should be handled for us */
+            }
+            C2sPacket::QueryRooms | C2sPacket::CreateRoom(_) | C2sPacket::JoinRoom(..) => {
+                /* already in a room */
+            }
+            C2sPacket::HostStartGame | C2sPacket::HostStartTournament(..) if !host => {
+                self.remove_player(
+                    addr,
+                    Some("Attempt to send host packet as non-host".to_string()),
+                    None,
+                )
+                .await;
+            }
             C2sPacket::HostStartGame => {
-                if !host {
-                    self.remove_player(
-                        addr,
-                        Some("Attempt to send host packet as non-host".to_string()),
-                        None,
-                    )
-                    .await;
-                    return;
-                }
-                self.game = Some(Game::new(
+                self.tournament = None;
+                self.tables = vec![Game::new(
+                    0,
                     self.players.iter().map(|p| p.name.to_string()).collect(),
-                ));
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::SyncGame(self.game.clone().unwrap()))
-                        .await;
-                }
+                )];
+                self.start_tables().await;
+            }
+            C2sPacket::HostStartTournament(table_size, mode) => {
+                let bracket = Bracket::new(
+                    self.players.iter().map(|p| p.name.to_string()).collect(),
+                    table_size,
+                    mode,
+                );
+                self.tables = bracket
+                    .round()
+                    .iter()
+                    .enumerate()
+                    .map(|(table, t)| Game::new(table, t.players.clone()))
+                    .collect();
+                self.tournament = Some(bracket);
+                self.sync_bracket().await;
+                self.start_tables().await;
             }
             C2sPacket::KeyPress(key) => {
                 let name = self.player_name(addr).unwrap_or_default();
-                let Some(game) = self.game.as_mut() else {
+                let Some(table) = self.table_of(&name).filter(|t| self.table_open(*t)) else {
                     return;
                 };
+                let game = &mut self.tables[table];
 
-                if game.current() == name {
+                if game.current().as_ref() == Some(&name) {
                     game.progress.insert(key, 0);
                 }
             }
             C2sPacket::KeyRelease(key) => {
                 let name = self.player_name(addr).unwrap_or_default();
-                let Some(game) = self.game.as_mut() else {
+                let Some(table) = self.table_of(&name).filter(|t| self.table_open(*t)) else {
                     return;
                 };
+                let game = &mut self.tables[table];
 
-                if game.current() == name {
+                if game.current().as_ref() == Some(&name) {
                     game.progress.remove(&key);
                 }
 
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::SyncGame(game.clone())).await;
-                }
+                let game = game.clone();
+                self.sync_table(&game).await;
             }
         }
     }
 
-    async fn tick(&mut self) {
-        let Some(mut game) = self.game.clone() else {
+    async fn tick(&mut self, table: usize) {
+        if !self.table_open(table) {
+            return;
+        }
+        let mut game = self.tables[table].clone();
+        let Some(current) = game.current() else {
+            return;
+        };
+        let Some(addr) = self.addr_from_name(&current) else {
             return;
         };
-        let addr = self.addr_from_name(&game.current()).unwrap();
 
         let mut should_update = false;
         let mut fired = None;
@@ -405,10 +1151,8 @@ impl Server {
             if *val == 255 {
                 fired = Some(*key);
             } else if *val == (15 * 3) {
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::PlaySound("/cock.ogg".to_string()))
-                        .await;
-                }
+                self.broadcast_table(table, S2cPacket::PlaySound(SoundEvent::Cock))
+                    .await;
             }
         }
 
@@ -418,37 +1162,21 @@ impl Server {
             game.advance();
 
             if fired == game.trigger_key {
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::PlaySound("/shoot.ogg".to_string()))
-                        .await;
-                }
+                self.broadcast_table(table, S2cPacket::PlaySound(SoundEvent::Shot))
+                    .await;
 
-                if self.player_mut(addr).unwrap().host {
-                    for sock in self
-                        .players
-                        .iter()
-                        .filter(|p| !p.host)
-                        .map(|p| p.sock_addr)
-                        .collect::<Vec<_>>()
-                    {
-                        self.remove_player(
-                            sock,
-                            Some("The host has died".to_string()),
-                            Some(&mut game),
-                        )
-                        .await;
+                match self.tournament.as_mut() {
+                    Some(bracket) if bracket.mode == BracketMode::Losers => {
+                        // The unlucky one moves on, everyone else at the table is safe
+                        bracket.finish_table(table, vec![current]);
+                        self.sync_bracket().await;
+                    }
+                    Some(_) => self.eliminate(addr, &mut game).await,
+                    None => {
+                        self.punish(addr, "You lost.", Some(&mut game)).await;
                     }
-                    tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
                 }
 
-                self.player_mut(addr)
-                    .unwrap()
-                    .send_packet(S2cPacket::KillYourselfNow)
-                    .await;
-
-                self.remove_player(addr, Some("You lost.".to_string()), Some(&mut game))
-                    .await;
-
                 if game.fired.len() >= 26 {
                     game.fired.clear();
                 }
@@ -458,8 +1186,10 @@ impl Server {
                     game.trigger_key = rand::thread_rng().gen_range('A'..='Z') as char;
                 }
 
-                if self.players.len() == 1 {
+                if self.tournament.is_none() && self.players.len() == 1 {
                     println!("last player");
+                    self.play_sound_for(self.players[0].sock_addr, SoundEvent::Win)
+                        .await;
                     self.remove_player(
                         self.players[0].sock_addr,
                         Some("You won :)".to_string()),
@@ -468,18 +1198,20 @@ impl Server {
                     .await;
                 }
             } else {
-                for pl in &mut self.players {
-                    pl.send_packet(S2cPacket::PlaySound("/missfire.ogg".to_string()))
-                        .await;
-                }
+                self.broadcast_table(table, S2cPacket::PlaySound(SoundEvent::Misfire))
+                    .await;
+            }
+
+            if self.table_open(table) {
+                self.announce_turn(&game).await;
             }
         }
 
         if should_update {
-            for pl in &mut self.players {
-                pl.send_packet(S2cPacket::SyncGame(game.clone())).await;
-            }
+            self.sync_table(&game).await;
+        }
+        if let Some(slot) = self.tables.get_mut(table) {
+            *slot = game;
         }
-        self.game = Some(game);
     }
 }
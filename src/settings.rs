@@ -0,0 +1,296 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+
+use crate::keyboard::KeyboardLayout;
+
+const FILE_NAME: &str = "settings.toml";
+/// Bump together with a new entry in `MIGRATIONS` whenever a setting is renamed or changes meaning.
+const VERSION: u32 = 1;
+/// `MIGRATIONS[i]` upgrades a version `i + 1` file to version `i + 2`.
+const MIGRATIONS: &[fn(&mut toml::Table)] = &[];
+
+/// What happens to this computer when you lose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Penalty {
+    /// A real blue screen. Only Windows has one, elsewhere this is the same as `Nothing`.
+    Bsod,
+    CloseGame,
+    /// Only get kicked from the table.
+    Nothing,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub username: String,
+    pub last_ip: String,
+    pub master_volume: f32,
+    pub effects_volume: f32,
+    pub music_volume: f32,
+    pub muted: bool,
+    /// Show sounds on screen too.
+    pub captions: bool,
+    pub caption_size: f32,
+    /// Seconds a caption stays up.
+    pub caption_duration: f32,
+    pub keyboard_layout: KeyboardLayout,
+    pub penalty: Penalty,
+    pub fullscreen: bool,
+    pub ui_scale: f32,
+    /// How to look up the public address, see `Resolver::parse`.
+    pub public_ip: String,
+    pub map_port: bool,
+    /// Folder of sounds to share when hosting, empty for none.
+    pub sound_pack: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            version: VERSION,
+            username: String::new(),
+            last_ip: String::new(),
+            master_volume: 1.0,
+            effects_volume: 1.0,
+            music_volume: 0.6,
+            muted: false,
+            captions: false,
+            caption_size: 32.0,
+            caption_duration: 2.5,
+            keyboard_layout: KeyboardLayout::Auto,
+            penalty: Penalty::Bsod,
+            fullscreen: false,
+            ui_scale: 1.0,
+            public_ip: "stun".to_string(),
+            map_port: true,
+            sound_pack: String::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// Pulls hand-edited numbers back into the ranges the settings window offers,
+    /// since a negative or NaN size would otherwise break drawing every frame.
+    fn clamp(&mut self) {
+        let default = Self::default();
+        let clamp = |value: &mut f32, min: f32, max: f32, default: f32| {
+            *value = if value.is_finite() {
+                value.clamp(min, max)
+            } else {
+                default
+            };
+        };
+        clamp(&mut self.master_volume, 0.0, 1.0, default.master_volume);
+        clamp(&mut self.effects_volume, 0.0, 1.0, default.effects_volume);
+        clamp(&mut self.music_volume, 0.0, 1.0, default.music_volume);
+        clamp(&mut self.caption_size, 16.0, 64.0, default.caption_size);
+        clamp(
+            &mut self.caption_duration,
+            1.0,
+            6.0,
+            default.caption_duration,
+        );
+        clamp(&mut self.ui_scale, 0.75, 2.0, default.ui_scale);
+    }
+}
+
+lazy_static! {
+    static ref SETTINGS: Mutex<Settings> = Mutex::new(Settings::default());
+}
+
+/// Path of a file in the per-user config directory (`$XDG_CONFIG_HOME/bluescreen-roulette` on Linux).
+pub fn config_path(file_name: &str) -> Option<PathBuf> {
+    Some(
+        dirs::config_dir()?
+            .join("bluescreen-roulette")
+            .join(file_name),
+    )
+}
+
+/// Reads the settings file into the global settings, migrating it from older versions.
+pub fn load() {
+    let Some(path) = config_path(FILE_NAME) else {
+        return;
+    };
+    if let Some(settings) = read(&path) {
+        *SETTINGS.lock().unwrap() = settings;
+    }
+}
+
+/// Reads a settings file, or `None` when there is none or it can't be used. An unreadable
+/// file is kept next to it as `settings.toml.bak`, since the next save overwrites it.
+fn read(path: &Path) -> Option<Settings> {
+    let text = std::fs::read_to_string(path).ok()?;
+    match parse(&text) {
+        Ok(settings) => Some(settings),
+        Err(e) => {
+            let backup = path.with_extension("toml.bak");
+            eprintln!(
+                "[Settings] ignoring unreadable {FILE_NAME}, keeping it as {}: {e}",
+                backup.display()
+            );
+            if let Err(e) = std::fs::copy(path, &backup) {
+                eprintln!("[Settings] could not back up {FILE_NAME}: {e}");
+            }
+            None
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Settings, toml::de::Error> {
+    let mut table = text.parse::<toml::Table>()?;
+
+    let version = table
+        .get("version")
+        .and_then(toml::Value::as_integer)
+        .map_or(1, |version| version.clamp(1, u32::MAX as i64) as u32);
+    if version > VERSION {
+        eprintln!("[Settings] {FILE_NAME} is from a newer version, some settings may be lost");
+    }
+    for migrate in MIGRATIONS.iter().skip(version as usize - 1) {
+        migrate(&mut table);
+    }
+    table.insert("version".to_string(), VERSION.into());
+
+    // Taken one by one, so a bad value only resets that setting instead of all of them
+    let mut valid = toml::Table::new();
+    for (key, value) in table {
+        let mut with_value = valid.clone();
+        with_value.insert(key.clone(), value);
+        match toml::Value::Table(with_value.clone()).try_into::<Settings>() {
+            Ok(_) => valid = with_value,
+            Err(e) => eprintln!("[Settings] resetting {key} in {FILE_NAME}: {e}"),
+        }
+    }
+    let mut settings = toml::Value::Table(valid).try_into::<Settings>()?;
+    settings.clamp();
+    Ok(settings)
+}
+
+pub fn get() -> Settings {
+    SETTINGS.lock().unwrap().clone()
+}
+
+/// Changes the settings without writing them, for values that change every frame
+/// while dragged. `save` them once the change is done.
+pub fn change(f: impl FnOnce(&mut Settings)) {
+    f(&mut SETTINGS.lock().unwrap());
+}
+
+/// Changes the settings and writes them to disk.
+pub fn update(f: impl FnOnce(&mut Settings)) {
+    let settings = {
+        let mut settings = SETTINGS.lock().unwrap();
+        f(&mut settings);
+        settings.clone()
+    };
+    // Written outside the lock so `get` never waits on the disk
+    write(&settings);
+}
+
+/// Writes the current settings to disk.
+pub fn save() {
+    write(&get());
+}
+
+fn write(settings: &Settings) {
+    let Some(path) = config_path(FILE_NAME) else {
+        return;
+    };
+    let res = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, toml::to_string(settings).unwrap()));
+    if let Err(e) = res {
+        eprintln!("[Settings] could not save {}: {e}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_file_keeps_the_defaults() {
+        let path = std::env::temp_dir().join("bluescreen-roulette-no-such-settings.toml");
+        assert_eq!(read(&path), None);
+    }
+
+    #[test]
+    fn unset_values_fall_back_to_defaults() {
+        let settings = parse("username = \"alice\"").unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                username: "alice".to_string(),
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn newer_versions_keep_the_settings_we_know() {
+        let settings = parse("version = 99\nusername = \"alice\"\nfuture_setting = 1").unwrap();
+        assert_eq!(settings.version, VERSION);
+        assert_eq!(settings.username, "alice");
+    }
+
+    #[test]
+    fn out_of_range_values_are_clamped() {
+        let settings = parse(
+            "master_volume = 5.0\n\
+             music_volume = -1.0\n\
+             caption_size = nan\n\
+             caption_duration = inf\n\
+             ui_scale = 0.1",
+        )
+        .unwrap();
+        assert_eq!(settings.master_volume, 1.0);
+        assert_eq!(settings.music_volume, 0.0);
+        assert_eq!(settings.caption_size, Settings::default().caption_size);
+        assert_eq!(
+            settings.caption_duration,
+            Settings::default().caption_duration
+        );
+        assert_eq!(settings.ui_scale, 0.75);
+    }
+
+    #[test]
+    fn bad_values_only_reset_that_setting() {
+        let settings = parse(
+            "username = \"alice\"\n\
+             master_volume = \"loud\"\n\
+             penalty = \"Explode\"\n\
+             music_volume = 0.5",
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            Settings {
+                username: "alice".to_string(),
+                music_volume: 0.5,
+                ..Settings::default()
+            }
+        );
+    }
+
+    #[test]
+    fn broken_files_are_backed_up() {
+        let dir = std::env::temp_dir().join("bluescreen-roulette-broken-settings");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(FILE_NAME);
+        std::fs::write(&path, "username = ").unwrap();
+        assert_eq!(read(&path), None);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("settings.toml.bak")).unwrap(),
+            "username = "
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
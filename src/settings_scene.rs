@@ -0,0 +1,256 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use egui_macroquad::{
+    egui::{self, Align2},
+    macroquad::prelude::*,
+};
+use lazy_static::lazy_static;
+
+use crate::{
+    keyboard::KeyboardLayout,
+    mods,
+    public_addr::{self, Resolver},
+    settings::{self, Penalty, Settings},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    Audio,
+    Display,
+    Input,
+    Mods,
+    Network,
+    Penalty,
+}
+
+/// The settings window, drawn on top of whichever scene is showing so connections stay alive.
+struct SettingsScene {
+    tab: Tab,
+    edit: Settings,
+    /// What the window last applied, so only fields edited here are written back.
+    applied: Settings,
+    /// Applied but not written to disk yet, which waits until the mouse is released.
+    unsaved: bool,
+}
+
+impl Drop for SettingsScene {
+    fn drop(&mut self) {
+        if self.unsaved {
+            settings::save();
+        }
+    }
+}
+
+lazy_static! {
+    static ref SCENE: Mutex<Option<SettingsScene>> = Mutex::new(None);
+}
+
+/// Whether a text field had the keyboard last frame. Escape takes the focus away from a
+/// field before `overlay` runs, so looking at this frame alone is not enough.
+static TYPING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_open() -> bool {
+    SCENE.lock().unwrap().is_some()
+}
+
+fn toggle() {
+    let mut scene = SCENE.lock().unwrap();
+    *scene = match scene.take() {
+        Some(_) => None,
+        None => Some(SettingsScene {
+            tab: Tab::Audio,
+            edit: settings::get(),
+            applied: settings::get(),
+            unsaved: false,
+        }),
+    };
+}
+
+/// Applies display settings and draws the settings button and window. Every scene calls this from its egui pass.
+pub fn overlay(ctx: &egui::Context) {
+    ctx.set_pixels_per_point(settings::get().ui_scale);
+
+    let typing = ctx.wants_keyboard_input();
+    let was_typing = TYPING.swap(typing, Ordering::Relaxed);
+    if is_key_pressed(KeyCode::Escape) && !typing && !was_typing {
+        toggle();
+    }
+    egui::Area::new("settings_button")
+        .anchor(Align2::RIGHT_TOP, (-8.0, 8.0))
+        .show(ctx, |ui| {
+            if ui.button("⚙ Settings").clicked() {
+                toggle();
+            }
+        });
+
+    let mut scene = SCENE.lock().unwrap();
+    let Some(current) = scene.as_mut() else {
+        return;
+    };
+    let mut open = true;
+    egui::Window::new("Settings")
+        .open(&mut open)
+        .collapsible(false)
+        .anchor(Align2::CENTER_CENTER, (0.0, 0.0))
+        .show(ctx, |ui| current.ui(ui));
+    if !open {
+        *scene = None;
+    }
+}
+
+impl SettingsScene {
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tab, Tab::Audio, "Audio");
+            ui.selectable_value(&mut self.tab, Tab::Display, "Display");
+            ui.selectable_value(&mut self.tab, Tab::Input, "Input");
+            ui.selectable_value(&mut self.tab, Tab::Mods, "Mods");
+            ui.selectable_value(&mut self.tab, Tab::Network, "Network");
+            ui.selectable_value(&mut self.tab, Tab::Penalty, "Penalty");
+        });
+        ui.separator();
+
+        let edit = &mut self.edit;
+        let mut public_ip_done = false;
+        match self.tab {
+            Tab::Audio => {
+                ui.checkbox(&mut edit.muted, "Mute");
+                ui.add_enabled_ui(!edit.muted, |ui| {
+                    ui.add(egui::Slider::new(&mut edit.master_volume, 0.0..=1.0).text("Master"));
+                    ui.add(egui::Slider::new(&mut edit.effects_volume, 0.0..=1.0).text("Effects"));
+                    ui.add(egui::Slider::new(&mut edit.music_volume, 0.0..=1.0).text("Music"));
+                });
+                ui.separator();
+                ui.checkbox(&mut edit.captions, "Show captions for sounds");
+                ui.add_enabled_ui(edit.captions, |ui| {
+                    ui.add(
+                        egui::Slider::new(&mut edit.caption_size, 16.0..=64.0).text("Caption size"),
+                    );
+                    ui.add(
+                        egui::Slider::new(&mut edit.caption_duration, 1.0..=6.0)
+                            .text("Caption duration")
+                            .suffix(" s"),
+                    );
+                });
+            }
+            Tab::Display => {
+                ui.checkbox(&mut edit.fullscreen, "Fullscreen");
+                ui.add(egui::Slider::new(&mut edit.ui_scale, 0.75..=2.0).text("Interface scale"));
+            }
+            Tab::Input => {
+                egui::ComboBox::from_label("Keyboard layout")
+                    .selected_text(edit.keyboard_layout.name())
+                    .show_ui(ui, |ui| {
+                        for layout in KeyboardLayout::ALL {
+                            ui.selectable_value(&mut edit.keyboard_layout, *layout, layout.name());
+                        }
+                    });
+            }
+            Tab::Mods => mods_ui(ui),
+            Tab::Network => {
+                ui.horizontal(|ui| {
+                    ui.label("Public IP lookup");
+                    // Also true when Enter is pressed
+                    public_ip_done = ui
+                        .add(
+                            egui::TextEdit::singleline(&mut edit.public_ip)
+                                .hint_text("stun, off or a URL"),
+                        )
+                        .lost_focus();
+                });
+                if Resolver::parse(&edit.public_ip).is_none() {
+                    ui.colored_label(egui::Color32::RED, "Use stun, stun:<servers>, off or a URL");
+                }
+                ui.checkbox(&mut edit.map_port, "Forward port when hosting");
+            }
+            Tab::Penalty => {
+                ui.label("When you lose:");
+                ui.radio_value(
+                    &mut edit.penalty,
+                    Penalty::Bsod,
+                    "Blue screen (Windows only)",
+                );
+                ui.radio_value(&mut edit.penalty, Penalty::CloseGame, "Close the game");
+                ui.radio_value(&mut edit.penalty, Penalty::Nothing, "Just leave the table");
+            }
+        }
+
+        if self.edit.fullscreen != self.applied.fullscreen {
+            set_fullscreen(self.edit.fullscreen);
+        }
+        if public_ip_done && self.edit.public_ip != self.applied.public_ip {
+            if let Some(resolver) = Resolver::parse(&self.edit.public_ip) {
+                public_addr::set_resolver(resolver);
+                self.apply(|s| &mut s.public_ip);
+            }
+        }
+        self.apply(|s| &mut s.muted);
+        self.apply(|s| &mut s.master_volume);
+        self.apply(|s| &mut s.effects_volume);
+        self.apply(|s| &mut s.music_volume);
+        self.apply(|s| &mut s.captions);
+        self.apply(|s| &mut s.caption_size);
+        self.apply(|s| &mut s.caption_duration);
+        self.apply(|s| &mut s.fullscreen);
+        self.apply(|s| &mut s.ui_scale);
+        self.apply(|s| &mut s.keyboard_layout);
+        self.apply(|s| &mut s.map_port);
+        self.apply(|s| &mut s.penalty);
+
+        if self.unsaved && !ui.input(|i| i.pointer.any_down()) {
+            settings::save();
+            self.unsaved = false;
+        }
+    }
+
+    /// Copies one field to the settings if it was edited here, leaving the
+    /// rest alone in case another scene changed them meanwhile.
+    fn apply<T: Clone + PartialEq>(&mut self, field: impl Fn(&mut Settings) -> &mut T) {
+        let value = field(&mut self.edit).clone();
+        if *field(&mut self.applied) == value {
+            return;
+        }
+        *field(&mut self.applied) = value.clone();
+        settings::change(|settings| *field(settings) = value);
+        self.unsaved = true;
+    }
+}
+
+fn mods_ui(ui: &mut egui::Ui) {
+    if let Some(folder) = mods::folder() {
+        ui.label(format!(
+            "Put .pfa archives or folders in {} and restart to load them.",
+            folder.display()
+        ));
+    }
+    let loaded = mods::loaded();
+    if loaded.is_empty() {
+        ui.label("No mods loaded.");
+        return;
+    }
+    ui.label("Later mods replace files from earlier ones:");
+    for m in loaded {
+        ui.group(|ui| {
+            let mut title = m.manifest.name.clone();
+            if !m.manifest.version.is_empty() {
+                title += &format!(" {}", m.manifest.version);
+            }
+            if !m.manifest.author.is_empty() {
+                title += &format!(" by {}", m.manifest.author);
+            }
+            ui.strong(title);
+            if !m.manifest.description.is_empty() {
+                ui.label(&m.manifest.description);
+            }
+            ui.small(format!(
+                "{} files, {} replaced, from {}",
+                m.files,
+                m.overrides,
+                m.source.display()
+            ));
+        });
+    }
+}
@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+/// Something the server wants players to hear. Clients pick the actual sound from their sound pack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SoundEvent {
+    /// A key has been held long enough to cock the revolver.
+    Cock,
+    Shot,
+    Misfire,
+    /// Sent only to the player whose turn it now is.
+    TurnStart,
+    Win,
+}
+
+impl SoundEvent {
+    pub const ALL: &'static [Self] = &[
+        Self::Cock,
+        Self::Shot,
+        Self::Misfire,
+        Self::TurnStart,
+        Self::Win,
+    ];
+
+    /// File name, without extension, of this event's sound in a sound pack folder.
+    pub fn file_stem(self) -> &'static str {
+        match self {
+            Self::Cock => "cock",
+            Self::Shot => "shot",
+            Self::Misfire => "misfire",
+            Self::TurnStart => "turn_start",
+            Self::Win => "win",
+        }
+    }
+
+    /// Whether everything else should get quieter while this plays.
+    pub fn ducks(self) -> bool {
+        self == Self::Shot
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::Sender;
+
+use crate::{audio, c2s_packet::C2sPacket, sound_event::SoundEvent};
+
+/// Bytes sent per `AssetChunk`, well under the frame size limit.
+pub const CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_ASSET_SIZE: u32 = 1024 * 1024;
+const MAX_PACK_SIZE: u32 = 4 * 1024 * 1024;
+const EXTENSIONS: &[&str] = &["ogg", "wav"];
+
+pub type Hash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoundAsset {
+    pub event: SoundEvent,
+    pub size: u32,
+    pub sha256: Hash,
+}
+
+/// The sounds a server wants played instead of each client's own.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoundPackManifest {
+    pub name: String,
+    pub assets: Vec<SoundAsset>,
+}
+
+impl SoundPackManifest {
+    fn check_limits(&self) -> Result<()> {
+        if self.assets.len() > SoundEvent::ALL.len() {
+            return Err(anyhow!("too many sounds ({})", self.assets.len()));
+        }
+        // An empty sound would never get a chunk, so its download could not finish
+        if let Some(asset) = self.assets.iter().find(|a| a.size == 0) {
+            return Err(anyhow!("the {:?} sound is empty", asset.event));
+        }
+        if let Some(asset) = self.assets.iter().find(|a| a.size > MAX_ASSET_SIZE) {
+            return Err(anyhow!(
+                "the {:?} sound is too large ({} bytes)",
+                asset.event,
+                asset.size
+            ));
+        }
+        let total: u32 = self.assets.iter().map(|a| a.size).sum();
+        if total > MAX_PACK_SIZE {
+            return Err(anyhow!("the pack is too large ({total} bytes)"));
+        }
+        Ok(())
+    }
+}
+
+/// A sound pack loaded by the server, ready to be sent to clients.
+#[derive(Debug)]
+pub struct HostedPack {
+    pub manifest: SoundPackManifest,
+    files: HashMap<Hash, Vec<u8>>,
+}
+
+impl HostedPack {
+    /// Loads a folder holding one file per event, named like `shot.ogg` or `turn_start.wav`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let name = dir.file_name().map_or("Sound pack".to_string(), |n| {
+            n.to_string_lossy().to_string()
+        });
+        let mut assets = vec![];
+        let mut files = HashMap::new();
+        for event in SoundEvent::ALL {
+            let Some(path) = EXTENSIONS
+                .iter()
+                .map(|ext| dir.join(format!("{}.{ext}", event.file_stem())))
+                .find(|path| path.is_file())
+            else {
+                continue;
+            };
+            let size = std::fs::metadata(&path)?.len();
+            if size == 0 {
+                return Err(anyhow!("{} is empty", path.display()));
+            }
+            if size > MAX_ASSET_SIZE as u64 {
+                return Err(anyhow!(
+                    "{} is too large ({size} bytes, at most {MAX_ASSET_SIZE})",
+                    path.display()
+                ));
+            }
+            let bytes = std::fs::read(&path)?;
+            let sha256: Hash = Sha256::digest(&bytes).into();
+            assets.push(SoundAsset {
+                event: *event,
+                size: bytes.len() as u32,
+                sha256,
+            });
+            files.insert(sha256, bytes);
+        }
+        if assets.is_empty() {
+            return Err(anyhow!("no sounds found in {}", dir.display()));
+        }
+
+        let manifest = SoundPackManifest { name, assets };
+        manifest.check_limits()?;
+        Ok(Self { manifest, files })
+    }
+
+    /// Up to `CHUNK_SIZE` bytes of an asset. Empty if there is no such asset or offset.
+    pub fn chunk(&self, sha256: &Hash, offset: u32) -> Vec<u8> {
+        let Some(file) = self.files.get(sha256) else {
+            return vec![];
+        };
+        let start = (offset as usize).min(file.len());
+        let end = (start + CHUNK_SIZE as usize).min(file.len());
+        file[start..end].to_vec()
+    }
+}
+
+/// The client's progress fetching the current server's pack.
+#[derive(Debug)]
+struct Download {
+    /// Finished sounds.
+    done: Vec<(SoundEvent, Vec<u8>)>,
+    /// Sounds still to fetch, the first is being received into `current`.
+    pending: Vec<SoundAsset>,
+    current: Vec<u8>,
+}
+
+lazy_static! {
+    static ref DOWNLOAD: Mutex<Option<Download>> = Mutex::new(None);
+}
+
+fn cache_path(sha256: &Hash) -> Option<PathBuf> {
+    let hex: String = sha256.iter().map(|b| format!("{b:02x}")).collect();
+    Some(
+        dirs::cache_dir()?
+            .join("bluescreen-roulette")
+            .join("sounds")
+            .join(hex),
+    )
+}
+
+fn read_cached(asset: &SoundAsset) -> Option<Vec<u8>> {
+    let bytes = std::fs::read(cache_path(&asset.sha256)?).ok()?;
+    (Sha256::digest(&bytes)[..] == asset.sha256).then_some(bytes)
+}
+
+fn write_cached(sha256: &Hash, bytes: &[u8]) {
+    let Some(path) = cache_path(sha256) else {
+        return;
+    };
+    let res = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, bytes));
+    if let Err(e) = res {
+        eprintln!("[Sound pack] could not cache {}: {e}", path.display());
+    }
+}
+
+/// Goes back to the built-in sounds and drops any unfinished download.
+pub fn reset() {
+    *DOWNLOAD.lock().unwrap() = None;
+    audio::use_pack(vec![]);
+}
+
+/// Switches to the server's pack, taking what it can from the cache and asking the server for the rest.
+pub fn start(tx: &Sender<C2sPacket>, manifest: Option<&SoundPackManifest>) {
+    reset();
+    let Some(manifest) = manifest else {
+        return;
+    };
+    if let Err(e) = manifest.check_limits() {
+        eprintln!("[Sound pack] ignoring {}: {e}", manifest.name);
+        return;
+    }
+
+    let mut download = Download {
+        done: vec![],
+        pending: vec![],
+        current: vec![],
+    };
+    for asset in &manifest.assets {
+        match read_cached(asset) {
+            Some(bytes) => download.done.push((asset.event, bytes)),
+            None => download.pending.push(asset.clone()),
+        }
+    }
+    println!(
+        "[Sound pack] using {}, {} of {} sounds cached",
+        manifest.name,
+        download.done.len(),
+        manifest.assets.len()
+    );
+    advance(tx, download);
+}
+
+/// Handles an `AssetChunk` from the server.
+pub fn receive(tx: &Sender<C2sPacket>, sha256: Hash, offset: u32, bytes: Vec<u8>) {
+    let Some(mut download) = DOWNLOAD.lock().unwrap().take() else {
+        return;
+    };
+    let Some(asset) = download.pending.first().cloned() else {
+        return;
+    };
+    if asset.sha256 != sha256 || offset as usize != download.current.len() {
+        // Left over from a pack we have since moved on from
+        *DOWNLOAD.lock().unwrap() = Some(download);
+        return;
+    }
+    if bytes.is_empty() || download.current.len() + bytes.len() > asset.size as usize {
+        eprintln!(
+            "[Sound pack] server sent a bad chunk for {:?}, keeping the built-in sounds",
+            asset.event
+        );
+        return;
+    }
+
+    download.current.extend(bytes);
+    if download.current.len() == asset.size as usize {
+        let bytes = std::mem::take(&mut download.current);
+        if Sha256::digest(&bytes)[..] != asset.sha256 {
+            eprintln!(
+                "[Sound pack] the {:?} sound does not match its hash, keeping the built-in sounds",
+                asset.event
+            );
+            return;
+        }
+        write_cached(&asset.sha256, &bytes);
+        download.done.push((asset.event, bytes));
+        download.pending.remove(0);
+    }
+    advance(tx, download);
+}
+
+/// Requests the next chunk, or hands the sounds to the mixer once everything is in.
+fn advance(tx: &Sender<C2sPacket>, download: Download) {
+    let Some(asset) = download.pending.first() else {
+        audio::use_pack(download.done);
+        return;
+    };
+    let request = C2sPacket::RequestAssetChunk(asset.sha256, download.current.len() as u32);
+    // Waits for room in the channel rather than dropping the request, which would stall the download
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        let _ = tx.send(request).await;
+    });
+    *DOWNLOAD.lock().unwrap() = Some(download);
+}
@@ -0,0 +1,264 @@
+use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BracketMode {
+    /// The last player standing at each table moves on.
+    Survivors,
+    /// The first player shot at each table moves on, the rest are safe.
+    Losers,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BracketTable {
+    pub players: Vec<String>,
+    pub advancing: Vec<String>,
+    pub out: Vec<String>,
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Bracket {
+    pub mode: BracketMode,
+    pub table_size: usize,
+    pub rounds: Vec<Vec<BracketTable>>,
+}
+
+impl Bracket {
+    pub fn new(mut players: Vec<String>, table_size: usize, mode: BracketMode) -> Self {
+        players.shuffle(&mut rand::thread_rng());
+        let table_size = table_size.max(2);
+        Self {
+            mode,
+            table_size,
+            rounds: vec![Self::split(players, table_size)],
+        }
+    }
+
+    /// Seats players at as many tables of at least `table_size` as possible,
+    /// spreading the remainder so that nobody ends up alone at a table.
+    fn split(players: Vec<String>, table_size: usize) -> Vec<BracketTable> {
+        let table_count = (players.len() / table_size).max(1);
+        let mut tables = vec![vec![]; table_count];
+        for (i, player) in players.into_iter().enumerate() {
+            tables[i % table_count].push(player);
+        }
+
+        tables
+            .into_iter()
+            .map(|players| BracketTable {
+                players,
+                advancing: vec![],
+                out: vec![],
+                finished: false,
+            })
+            .collect()
+    }
+
+    pub fn round(&self) -> &[BracketTable] {
+        self.rounds.last().map(Vec::as_slice).unwrap_or_default()
+    }
+
+    fn round_mut(&mut self) -> &mut [BracketTable] {
+        self.rounds
+            .last_mut()
+            .map(Vec::as_mut_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn table_of(&self, name: &str) -> Option<usize> {
+        self.round()
+            .iter()
+            .position(|t| t.players.iter().any(|p| p == name))
+    }
+
+    /// Whether `name` has been knocked out, this round or an earlier one. In `Survivors`
+    /// that includes everyone whose table finished without them.
+    pub fn is_out(&self, name: &str) -> bool {
+        let knocked_out = self
+            .rounds
+            .iter()
+            .flatten()
+            .any(|t| t.out.iter().any(|p| p == name));
+        knocked_out || (self.mode == BracketMode::Survivors && self.left_behind(name))
+    }
+
+    /// Whether `name` made it through a `Losers` table without being shot and is done playing.
+    pub fn is_safe(&self, name: &str) -> bool {
+        self.mode == BracketMode::Losers && !self.is_out(name) && self.left_behind(name)
+    }
+
+    /// Whether a table `name` sat at finished without them moving on.
+    fn left_behind(&self, name: &str) -> bool {
+        self.rounds.iter().flatten().any(|t| {
+            t.finished
+                && t.players.iter().any(|p| p == name)
+                && !t.advancing.iter().any(|p| p == name)
+        })
+    }
+
+    pub fn is_open(&self, table: usize) -> bool {
+        self.round().get(table).is_some_and(|t| !t.finished)
+    }
+
+    pub fn knock_out(&mut self, name: &str) {
+        for table in self.round_mut() {
+            if !table.players.iter().any(|p| p == name) {
+                continue;
+            }
+
+            table.advancing.retain(|p| p != name);
+            if !table.out.iter().any(|p| p == name) {
+                table.out.push(name.to_string());
+            }
+        }
+    }
+
+    pub fn finish_table(&mut self, table: usize, advancing: Vec<String>) {
+        let Some(table) = self.round_mut().get_mut(table) else {
+            return;
+        };
+        if table.finished {
+            return;
+        }
+
+        table.advancing = advancing;
+        table.finished = true;
+    }
+
+    pub fn round_finished(&self) -> bool {
+        self.round().iter().all(|t| t.finished)
+    }
+
+    pub fn advancing(&self) -> Vec<String> {
+        self.round()
+            .iter()
+            .flat_map(|t| t.advancing.iter().cloned())
+            .collect()
+    }
+
+    pub fn next_round(&mut self) {
+        let advancing = self.advancing();
+        self.rounds.push(Self::split(advancing, self.table_size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("player{i}")).collect()
+    }
+
+    /// Finishes every table of the current round with its first player advancing.
+    fn finish_round(bracket: &mut Bracket) {
+        for table in 0..bracket.round().len() {
+            let first = bracket.round()[table].players[0].clone();
+            bracket.finish_table(table, vec![first]);
+        }
+    }
+
+    #[test]
+    fn knocked_out_players_stay_out_in_later_rounds() {
+        let mut bracket = Bracket::new(players(4), 2, BracketMode::Survivors);
+        let loser = bracket.round()[0].players[1].clone();
+        assert!(!bracket.is_out(&loser));
+        bracket.knock_out(&loser);
+        assert!(bracket.is_out(&loser));
+
+        finish_round(&mut bracket);
+        bracket.next_round();
+        assert!(bracket.is_out(&loser));
+        assert!(!bracket.is_out(&bracket.round()[0].players[0]));
+        assert!(!bracket.is_out("spectator who never played"));
+    }
+
+    #[test]
+    fn players_not_shot_in_losers_mode_are_safe() {
+        let mut bracket = Bracket::new(players(4), 2, BracketMode::Losers);
+        let table = bracket.round()[0].players.clone();
+        let (shot, spared) = (&table[0], &table[1]);
+        bracket.finish_table(0, vec![shot.clone()]);
+        assert!(bracket.is_safe(spared));
+        assert!(!bracket.is_out(spared));
+        assert!(!bracket.is_safe(shot));
+        assert!(!bracket.is_out(shot));
+
+        finish_round(&mut bracket);
+        bracket.next_round();
+        assert!(bracket.is_safe(spared));
+        assert!(!bracket.is_safe(shot));
+
+        let other = bracket.round()[0].players[1].clone();
+        bracket.knock_out(&other);
+        assert!(bracket.is_out(&other));
+        assert!(!bracket.is_safe(&other));
+    }
+
+    #[test]
+    fn odd_player_counts_leave_nobody_alone() {
+        for count in [3, 5, 7, 9, 11] {
+            let bracket = Bracket::new(players(count), 2, BracketMode::Survivors);
+            let round = bracket.round();
+            assert!(
+                round.iter().all(|t| t.players.len() >= 2),
+                "{count} players"
+            );
+            let mut seated: Vec<_> = round.iter().flat_map(|t| t.players.clone()).collect();
+            seated.sort();
+            let mut expected = players(count);
+            expected.sort();
+            assert_eq!(seated, expected);
+        }
+    }
+
+    #[test]
+    fn fewer_players_than_a_table_share_one() {
+        let bracket = Bracket::new(players(3), 4, BracketMode::Losers);
+        assert_eq!(bracket.round().len(), 1);
+        assert_eq!(bracket.round()[0].players.len(), 3);
+    }
+
+    #[test]
+    fn rounds_advance_to_a_single_final_table() {
+        let mut bracket = Bracket::new(players(7), 2, BracketMode::Survivors);
+        assert_eq!(bracket.round().len(), 3);
+
+        finish_round(&mut bracket);
+        assert!(bracket.round_finished());
+        assert_eq!(bracket.advancing().len(), 3);
+
+        bracket.next_round();
+        assert_eq!(bracket.rounds.len(), 2);
+        assert_eq!(bracket.round().len(), 1);
+        assert!(!bracket.round_finished());
+
+        finish_round(&mut bracket);
+        assert!(bracket.round_finished());
+        assert_eq!(bracket.advancing().len(), 1);
+    }
+
+    #[test]
+    fn finished_tables_stay_finished() {
+        let mut bracket = Bracket::new(players(4), 2, BracketMode::Survivors);
+        let winner = bracket.round()[0].players[0].clone();
+        bracket.finish_table(0, vec![winner.clone()]);
+        bracket.finish_table(0, vec![]);
+        assert_eq!(bracket.round()[0].advancing, vec![winner]);
+        assert!(!bracket.is_open(0));
+        assert!(bracket.is_open(1));
+        assert!(!bracket.round_finished());
+    }
+
+    #[test]
+    fn knocked_out_players_stop_advancing() {
+        let mut bracket = Bracket::new(players(4), 2, BracketMode::Survivors);
+        let table = bracket.round()[0].players.clone();
+        bracket.finish_table(0, table.clone());
+        bracket.knock_out(&table[1]);
+        assert_eq!(bracket.round()[0].advancing, vec![table[0].clone()]);
+        assert_eq!(bracket.round()[0].out, vec![table[1].clone()]);
+        assert_eq!(bracket.table_of(&table[1]), Some(0));
+    }
+}